@@ -7,6 +7,10 @@ module.exports = {
   options: {
     root: __dirname,
     mains: {
+      credentials: {
+        entry: 'credentials',
+        title: 'denViews account'
+      },
       dash: {
         entry: 'dash',
         title: 'denViews dashboard'
@@ -15,9 +19,17 @@ module.exports = {
         entry: 'init',
         title: 'denViews init'
       },
+      login: {
+        entry: 'login',
+        title: 'denViews login'
+      },
       settings: {
         entry: 'settings',
         title: 'denViews settings'
+      },
+      tokens: {
+        entry: 'tokens',
+        title: 'denViews API tokens'
       }
     }
   },
@@ -12,6 +12,7 @@ const FolderConsumer = (props) => {
           ? <p>No parent folder.</p>
           : <p>Parent folder: <FolderLink folderID={props.data.parent_id} /></p>
       }
+      <p className='pb-4'>{props.data.views} views, {props.data.hits} hits in total</p>
       <PartialFolderTable folders={props.data.folders} />
       <PageInfoList pages={props.data.pages} folderID={props.data.id} />
     </div>
@@ -25,11 +26,13 @@ const PartialFolderTable = (props) =>
       <tr>
         <th className='border px-2'>ID</th>
         <th className='border px-2'>Name</th>
+        <th className='border px-2'>Views</th>
+        <th className='border px-2'>Hits</th>
       </tr>
     </thead>
     <tbody>
       {props.folders.map((e) =>
-        <PartialFolderInfo key={e.name + e.id} id={e.id} name={e.name} />)}
+        <PartialFolderInfo key={e.name + e.id} id={e.id} name={e.name} views={e.views} hits={e.hits} />)}
     </tbody>
   </table>
 
@@ -39,11 +42,15 @@ const PartialFolderInfo = (props) =>
   <tr>
     <td className='border px-2'><FolderLink folderID={props.id} /></td>
     <td className='border px-2'>{props.name}</td>
+    <td className='border px-2'>{props.views}</td>
+    <td className='border px-2'>{props.hits}</td>
   </tr>
 
 PartialFolderInfo.propTypes = {
   id: PropTypes.number,
-  name: PropTypes.string
+  name: PropTypes.string,
+  views: PropTypes.number,
+  hits: PropTypes.number
 }
 
 const PageInfoList = (props) =>
@@ -100,6 +107,8 @@ FolderConsumer.propTypes = {
   data: {
     id: PropTypes.number,
     name: PropTypes.string,
+    views: PropTypes.number,
+    hits: PropTypes.number,
     folders: PropTypes.array,
     pages: PropTypes.array
   }
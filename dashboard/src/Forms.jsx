@@ -10,6 +10,10 @@ function JSONForm (props) {
     )
   })
 
+  if (props.csrfToken !== undefined && props.csrfToken !== null) {
+    components.push(<input key='formCsrf' type='hidden' name='csrf_token' value={props.csrfToken} />)
+  }
+
   components.push(<input key='formSubmit' type='submit' />)
 
   return (
@@ -22,10 +26,15 @@ function JSONForm (props) {
 JSONForm.propTypes = {
   data: PropTypes.object,
   method: PropTypes.string,
-  action: PropTypes.string
+  action: PropTypes.string,
+  csrfToken: PropTypes.string
 }
 
 function elementToFormComponent (element) {
+  if (Array.isArray(element[1])) {
+    return <ArrayInput name={element[0]} input={element[1]} />
+  }
+
   switch (typeof element[1]) {
     case 'string':
       return <StringInput name={element[0]} input={element[1]} />
@@ -46,6 +55,21 @@ StringInput.propTypes = {
   input: PropTypes.string
 }
 
+// One text input per entry, plus an empty one to add another.
+// Entries left empty are dropped by the server.
+const ArrayInput = (props) =>
+  <fieldset>
+    <legend>{props.name}</legend>
+    {props.input.concat(['']).map((v, i) =>
+      <input key={i} type='text' name={props.name + '[' + i + ']'} defaultValue={v} />
+    )}
+  </fieldset>
+
+ArrayInput.propTypes = {
+  name: PropTypes.string,
+  input: PropTypes.array
+}
+
 const NumberInput = (props) =>
   <label>{props.name}: <input type='number' name={props.name} defaultValue={props.input} /></label>
 
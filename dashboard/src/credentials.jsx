@@ -0,0 +1,65 @@
+import React from 'react'
+import { render } from 'react-dom'
+import * as utils from './util'
+import 'tailwindcss/tailwind.css'
+
+class CredentialsForm extends React.Component {
+  constructor (props) {
+    super(props)
+    this.state = { current_pass: '', user: '', pass: '', confirm: '', message: null }
+
+    this.handleChange = this.handleChange.bind(this)
+    this.handleSubmit = this.handleSubmit.bind(this)
+  }
+
+  handleChange (event) {
+    this.setState({ [event.target.name]: event.target.value })
+  }
+
+  async handleSubmit (event) {
+    event.preventDefault()
+    if (this.state.pass !== this.state.confirm) {
+      this.setState({ message: 'The new passwords do not match.' })
+      return
+    }
+
+    const body = new URLSearchParams()
+    body.append('current_pass', this.state.current_pass)
+    body.append('user', this.state.user)
+    body.append('pass', this.state.pass)
+
+    const res = await utils.csrfFetch('/_denViews_dash/api/credentials', { method: 'POST', body: body })
+    switch (res.status) {
+      case 200:
+        window.location.assign('/_denViews_dash/login')
+        break
+      case 403:
+        this.setState({ message: 'The current password is incorrect.' })
+        break
+      default:
+        this.setState({ message: (await res.json()).message })
+    }
+  }
+
+  render () {
+    return (
+      <form onSubmit={this.handleSubmit}>
+        {this.state.message === null ? null : <p className='text-red-500 mb-4'>{this.state.message}</p>}
+        <p className='mb-4'>Changing credentials logs out every session and revokes every API token.</p>
+        <div><label>current password: <input type='password' name='current_pass' autoComplete='current-password' onChange={this.handleChange} /></label></div>
+        <div><label>new user (optional): <input type='text' name='user' autoComplete='username' onChange={this.handleChange} /></label></div>
+        <div><label>new password: <input type='password' name='pass' autoComplete='new-password' onChange={this.handleChange} /></label></div>
+        <div><label>confirm new password: <input type='password' name='confirm' autoComplete='new-password' onChange={this.handleChange} /></label></div>
+        <input type='submit' value='change credentials' />
+      </form>
+    )
+  }
+}
+
+const Credentials = () =>
+  <div className='md:container md:mt-4 md:rounded md:shadow-lg mx-auto p-4'>
+    <utils.DenViewsHeader />
+    <CredentialsForm />
+  </div>
+
+render(<Credentials />, document.getElementById('root'))
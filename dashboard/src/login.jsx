@@ -0,0 +1,23 @@
+import React from 'react'
+import { render } from 'react-dom'
+import 'tailwindcss/tailwind.css'
+
+const Login = () => {
+  const failed = new URL(document.URL).searchParams.get('failed') !== null
+
+  return (
+    <div className='md:container md:mt-4 md:rounded md:shadow-lg mx-auto p-4'>
+      <div className='border-b-2 mb-4'>
+        <h1 className='text-xl'>denViews</h1>
+      </div>
+      {failed ? <p className='text-red-500 mb-4'>Incorrect username or password.</p> : null}
+      <form method='POST' action='/_denViews_dash/api/login'>
+        <div><label>user: <input type='text' name='user' autoComplete='username' /></label></div>
+        <div><label>pass: <input type='password' name='pass' autoComplete='current-password' /></label></div>
+        <input type='submit' value='log in' />
+      </form>
+    </div>
+  )
+}
+
+render(<Login />, document.getElementById('root'))
@@ -1,16 +1,27 @@
 import React from 'react'
 import { render } from 'react-dom'
+import PropTypes from 'prop-types'
 import * as utils from './util'
 import JSONForm from './Forms'
 
+const SettingsConsumer = (props) =>
+  <JSONForm data={props.data.settings} csrfToken={props.data.csrfToken} method='POST' action='/_denViews_dash/api/settings' />
+
+SettingsConsumer.propTypes = {
+  data: PropTypes.object
+}
+
 const SettingsForm = (props) => {
   const Form = utils.futureWrapper(
-    JSONForm,
+    SettingsConsumer,
     utils.Loading,
-    utils.fetchFromApi('settings', null)
+    async () => ({
+      settings: await utils.fetchFromApi('settings', null)(),
+      csrfToken: await utils.fetchCsrfToken()
+    })
   )
 
-  return <Form method='POST' action='/_denViews_dash/api/settings' />
+  return <Form />
 }
 
 const Settings = () => <SettingsForm />
@@ -0,0 +1,122 @@
+import React from 'react'
+import { render } from 'react-dom'
+import PropTypes from 'prop-types'
+import * as utils from './util'
+import 'tailwindcss/tailwind.css'
+
+const SCOPES = ['read:stats', 'write:flush', 'read:metrics', 'admin']
+
+const TokenTable = (props) =>
+  <table className='border mb-4'>
+    <thead>
+      <tr>
+        <th className='border px-2'>ID</th>
+        <th className='border px-2'>Name</th>
+        <th className='border px-2'>Scopes</th>
+        <th className='border px-2'>Created</th>
+        <th className='border px-2'>Last used</th>
+        <th className='border px-2' />
+      </tr>
+    </thead>
+    <tbody>
+      {props.data.map((e) => <TokenInfo key={e.id} token={e} />)}
+    </tbody>
+  </table>
+
+TokenTable.propTypes = { data: PropTypes.array }
+
+const TokenInfo = (props) =>
+  <tr>
+    <td className='border px-2'>{props.token.id}</td>
+    <td className='border px-2'>{props.token.name}</td>
+    <td className='border px-2'>{props.token.scopes.join(', ')}</td>
+    <td className='border px-2'>{utils.formatTime(props.token.created)}</td>
+    <td className='border px-2'>{props.token.last_used === null ? 'never' : utils.formatTime(props.token.last_used)}</td>
+    <td className='border px-2'><RevokeButton tokenID={props.token.id} /></td>
+  </tr>
+
+TokenInfo.propTypes = { token: PropTypes.object }
+
+const RevokeButton = (props) => {
+  const revoke = async () => {
+    const searchParams = new URLSearchParams()
+    searchParams.append('token_id', props.tokenID)
+    await utils.csrfFetch('/_denViews_dash/api/tokens?' + searchParams.toString(), { method: 'DELETE' })
+    window.location.reload()
+  }
+
+  return <button className='text-red-500' onClick={revoke}>revoke</button>
+}
+
+RevokeButton.propTypes = { tokenID: PropTypes.number }
+
+class TokenCreator extends React.Component {
+  constructor (props) {
+    super(props)
+    this.state = { name: '', scopes: [], created: null }
+
+    this.handleName = this.handleName.bind(this)
+    this.handleScope = this.handleScope.bind(this)
+    this.handleSubmit = this.handleSubmit.bind(this)
+  }
+
+  handleName (event) {
+    this.setState({ name: event.target.value })
+  }
+
+  handleScope (event) {
+    const scopes = this.state.scopes.filter((s) => s !== event.target.value)
+    if (event.target.checked) {
+      scopes.push(event.target.value)
+    }
+    this.setState({ scopes: scopes })
+  }
+
+  async handleSubmit (event) {
+    event.preventDefault()
+    const body = new URLSearchParams()
+    body.append('name', this.state.name)
+    this.state.scopes.forEach((s, i) => body.append('scopes[' + i + ']', s))
+
+    const res = await utils.csrfFetch('/_denViews_dash/api/tokens', { method: 'POST', body: body })
+    if (res.ok) {
+      this.setState({ created: await res.json() })
+    }
+  }
+
+  render () {
+    return (
+      <div>
+        {this.state.created === null
+          ? null
+          : <p className='mb-4'>Token <b>{this.state.created.name}</b> created: <code>{this.state.created.token}</code> (this will not be shown again)</p>}
+        <form onSubmit={this.handleSubmit}>
+          <label>name: <input type='text' value={this.state.name} onChange={this.handleName} /></label>
+          {SCOPES.map((s) =>
+            <label key={s}>
+              <input type='checkbox' value={s} onChange={this.handleScope} />{s}
+            </label>)}
+          <input type='submit' value='create' />
+        </form>
+      </div>
+    )
+  }
+}
+
+const Tokens = () => {
+  const Table = utils.futureWrapper(
+    TokenTable,
+    utils.Loading,
+    utils.fetchFromApi('tokens', null)
+  )
+
+  return (
+    <div className='md:container md:mt-4 md:rounded md:shadow-lg mx-auto p-4'>
+      <utils.DenViewsHeader />
+      <Table />
+      <TokenCreator />
+    </div>
+  )
+}
+
+render(<Tokens />, document.getElementById('root'))
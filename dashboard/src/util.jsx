@@ -2,10 +2,41 @@ import React from 'react'
 import 'tailwindcss/tailwind.css'
 
 const DenViewsHeader = () =>
-  <div className='border-b-2 mb-4'>
+  <div className='border-b-2 mb-4 flex justify-between'>
     <h1 className='text-xl'>denViews</h1>
+    <nav>
+      <a href='/_denViews_dash/dash' className='text-blue-500 px-2'>dashboard</a>
+      <a href='/_denViews_dash/settings' className='text-blue-500 px-2'>settings</a>
+      <a href='/_denViews_dash/tokens' className='text-blue-500 px-2'>tokens</a>
+      <a href='/_denViews_dash/credentials' className='text-blue-500 px-2'>account</a>
+      <button onClick={logout} className='text-blue-500 px-2'>log out</button>
+    </nav>
   </div>
 
+async function logout () {
+  await csrfFetch('/_denViews_dash/api/logout', { method: 'POST' })
+  window.location.assign('/_denViews_dash/login')
+}
+
+// The CSRF token for the current login session, or null when the
+// dashboard is being accessed some other way (e.g., Basic auth).
+async function fetchCsrfToken () {
+  const res = await fetch('/_denViews_dash/api/session')
+  return res.ok ? (await res.json()).csrf_token : null
+}
+
+// fetch(), but with the session's CSRF token attached. Anything that
+// isn't a GET to the dashboard API needs to go through this.
+async function csrfFetch (url, options) {
+  const token = await fetchCsrfToken()
+  const headers = new Headers(options.headers)
+  if (token !== null) {
+    headers.append('X-CSRF-Token', token)
+  }
+
+  return fetch(url, { ...options, headers: headers })
+}
+
 function fetchFromApi (route, searchParams) {
   return async () => {
     const currentUrl = new URL(document.URL)
@@ -21,6 +52,8 @@ function fetchFromApi (route, searchParams) {
 
 const Loading = () => <p>Loading...</p>
 
+const formatTime = (seconds) => new Date(seconds * 1000).toLocaleString()
+
 function futureWrapper (Component, Loader, future) {
   return class extends React.Component {
     constructor (props) {
@@ -51,4 +84,4 @@ function futureWrapper (Component, Loader, future) {
   }
 }
 
-export { DenViewsHeader, fetchFromApi, futureWrapper, Loading }
+export { DenViewsHeader, csrfFetch, fetchCsrfToken, fetchFromApi, formatTime, futureWrapper, Loading }
@@ -8,7 +8,8 @@
 
 use crate::database::util;
 use crate::database::{Database, DatabaseOperation};
-use crate::database::{DenViewSettings, ViewRecord};
+use crate::database::{DenViewSettings, PoolStatus, ViewRecord};
+use crate::error;
 use crate::Error;
 use bb8::Pool;
 use bb8_postgres::{
@@ -16,14 +17,13 @@ use bb8_postgres::{
     PostgresConnectionManager,
 };
 use chrono::{offset::Utc, DateTime};
-use crypto::digest::Digest;
-use crypto::sha3::Sha3;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
-use sqlx::{mysql, ConnectOptions, Row};
+use sqlx::{mysql, ConnectOptions, Connection, Row};
 use std::time::SystemTime;
 
 pub struct MariaDB {
     db_pool: mysql::MySqlPool,
+    pool_max: u32,
 }
 
 impl MariaDB {
@@ -50,7 +50,10 @@ impl MariaDB {
             .execute(&db_pool)
             .await?;
 
-        Ok(MariaDB { db_pool })
+        Ok(MariaDB {
+            db_pool,
+            pool_max: pool_amount,
+        })
     }
 }
 
@@ -60,7 +63,7 @@ impl Database for MariaDB {
         match op {
             DatabaseOperation::Get(path) => Ok(Some(self.get_page_info(path).await?)),
             DatabaseOperation::UpdatePage(path, info) => {
-                self.append_visitor(path, info).await?;
+                self.append_visitor(path, *info).await?;
                 Ok(None)
             }
             /*
@@ -81,6 +84,115 @@ impl Database for MariaDB {
         }
     }
 
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.db_pool).await?;
+
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.db_pool.close().await;
+    }
+
+    async fn get_views(&self, paths: &[String]) -> Result<Vec<ViewRecord>, Error> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // the same sums as the path_ views, for every path at once
+        let sql = format!(
+            "
+            SELECT
+                paths.path,
+                CAST(pages.total_views + (
+                    SELECT COUNT(visitor_id)
+                    FROM page_visitors
+                    WHERE page_visitors.page_id = pages.page_id
+                ) AS SIGNED),
+                CAST(pages.total_hits + COALESCE((
+                    SELECT SUM(visitor_hits)
+                    FROM page_visitors
+                    WHERE page_visitors.page_id = pages.page_id
+                ), 0) AS SIGNED)
+            FROM paths
+            INNER JOIN pages
+            ON pages.path_id = paths.path_id
+            WHERE paths.path IN ({})
+            ",
+            vec!["?"; paths.len()].join(", ")
+        );
+
+        let mut query = sqlx::query(&sql);
+        for path in paths {
+            query = query.bind(path);
+        }
+
+        Ok(query
+            .fetch_all(&self.db_pool)
+            .await?
+            .iter()
+            .map(|r| ViewRecord {
+                page: r.get(0),
+                views: r.get(1),
+                hits: r.get(2),
+            })
+            .collect())
+    }
+
+    async fn purge(&self, daily_days: u64, monthly_months: u64) -> Result<(), Error> {
+        let mut transaction = self.db_pool.begin().await?;
+
+        if daily_days > 0 {
+            sqlx::query(
+                "
+                INSERT INTO page_history_monthly (page_id, month, views, hits)
+                SELECT page_id, DATE_FORMAT(day, '%Y-%m-01'), SUM(views), SUM(hits)
+                FROM page_history
+                WHERE day < CURRENT_DATE - INTERVAL ? DAY
+                GROUP BY page_id, DATE_FORMAT(day, '%Y-%m-01')
+                ON DUPLICATE KEY UPDATE
+                    views = page_history_monthly.views + VALUES(views),
+                    hits = page_history_monthly.hits + VALUES(hits)
+                ",
+            )
+            .bind(daily_days)
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+            sqlx::query("DELETE FROM page_history WHERE day < CURRENT_DATE - INTERVAL ? DAY")
+                .bind(daily_days)
+                .execute(&mut transaction)
+                .await?;
+            log::info!("rolled up daily history older than {} days", daily_days);
+        }
+
+        if monthly_months > 0 {
+            let deleted = sqlx::query(
+                "
+                DELETE FROM page_history_monthly
+                WHERE month < DATE_FORMAT(CURRENT_DATE, '%Y-%m-01') - INTERVAL ? MONTH
+                ",
+            )
+            .bind(monthly_months)
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+            log::info!("deleted {} expired monthly totals", deleted);
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus {
+            size: self.db_pool.size(),
+            idle: self.db_pool.num_idle() as u32,
+            max: self.pool_max,
+        }
+    }
+
     async fn get_settings(&self) -> Result<DenViewSettings, Error> {
         let settings =
             sqlx::query("SELECT setting FROM settings WHERE setting_name = 'current_settings'")
@@ -102,11 +214,14 @@ impl MariaDB {
         // this should ONLY RETURN a single row, ALWAYS
         // any other result is completely wrong
 
-        let path_id: i32 = sqlx::query("SELECT path_id FROM paths WHERE path = ?")
+        let path_id: i32 = match sqlx::query("SELECT path_id FROM paths WHERE path = ?")
             .bind(&path)
-            .fetch_one(&self.db_pool)
+            .fetch_optional(&self.db_pool)
             .await?
-            .get(0);
+        {
+            Some(row) => row.get(0),
+            None => return error::not_found(format!("{} has not been tracked", path)),
+        };
 
         // This is only safe because of the path_id abstraction that occurs.
         // This should not be replicated in any other circumstance.
@@ -122,7 +237,7 @@ impl MariaDB {
         })
     }
 
-    async fn append_visitor(&self, path: &str, visitor_info: &str) -> Result<(), Error> {
+    async fn append_visitor(&self, path: &str, visitor_info: Option<&str>) -> Result<(), Error> {
         log::debug!("recording new visitor");
         // either get a page ID, or create it
         let page_id: i32 = sqlx::query(
@@ -142,14 +257,28 @@ impl MariaDB {
         .unwrap_or(self.create_page(path).await?)
         .get(0);
 
-        let mut hasher = Sha3::sha3_256();
+        // anonymous hits are counted in hit_count, but never in view_count,
+        // since that only counts visitor IDs
+        //
+        // every row is put on the day it was added, which is the day it's
+        // flushed into the page's history under; a visitor's later hits go
+        // on the same row, and so the same day, as their view
+        let visitor_info = match visitor_info {
+            Some(v) => v,
+            None => {
+                sqlx::query("INSERT INTO page_visitors (visitor_id, page_id, day) VALUES (NULL, ?, CURRENT_DATE)")
+                    .bind(page_id)
+                    .execute(&self.db_pool)
+                    .await?;
+                return Ok(());
+            }
+        };
+
         let salt: String = sqlx::query("SELECT salt FROM salt")
             .fetch_one(&self.db_pool)
             .await?
             .get(0);
-        hasher.input_str(&(visitor_info.to_string() + &salt));
-        let visitor_hash = hasher.result_str();
-        println!("{}", visitor_hash);
+        let visitor_hash = util::visitor_hash(&salt, visitor_info);
 
         // optional! this is because if the visitor doesn't already exist, it is instead
         // added into the visitors table
@@ -190,7 +319,7 @@ impl MariaDB {
                 .execute(&self.db_pool)
                 .await?;
             } else {
-                sqlx::query("INSERT INTO page_visitors (visitor_id, page_id) VALUES (?, ?)")
+                sqlx::query("INSERT INTO page_visitors (visitor_id, page_id, day) VALUES (?, ?, CURRENT_DATE)")
                     .bind(&id)
                     .bind(&page_id)
                     .execute(&self.db_pool)
@@ -201,11 +330,13 @@ impl MariaDB {
                 .bind(&visitor_hash)
                 .execute(&self.db_pool)
                 .await?;
-            sqlx::query("INSERT INTO page_visitors (visitor_id, page_id) VALUES (?, ?)")
-                .bind(&visitor_hash)
-                .bind(&page_id)
-                .execute(&self.db_pool)
-                .await?;
+            sqlx::query(
+                "INSERT INTO page_visitors (visitor_id, page_id, day) VALUES (?, ?, CURRENT_DATE)",
+            )
+            .bind(&visitor_hash)
+            .bind(&page_id)
+            .execute(&self.db_pool)
+            .await?;
         }
 
         Ok(())
@@ -336,10 +467,37 @@ impl MariaDB {
     async fn flush(&self) -> Result<(), Error> {
         log::info!("flushing page_visitors to database now...");
 
+        // hits wait until the flush is done, so the totals, the history and
+        // what's deleted all come from the same rows: a locking read of the
+        // whole table under REPEATABLE READ locks the gaps between rows too,
+        // which keeps new ones out
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+            .execute(&mut conn)
+            .await?;
+        let mut transaction = conn.begin().await?;
+
+        sqlx::query("SELECT page_id FROM page_visitors FOR UPDATE")
+            .fetch_all(&mut transaction)
+            .await?;
         let views = sqlx::query("SELECT * FROM total_views")
-            .fetch_all(&self.db_pool)
+            .fetch_all(&mut transaction)
             .await?;
-        let mut transaction = self.db_pool.begin().await?;
+
+        sqlx::query(
+            "
+            INSERT INTO page_history (page_id, day, views, hits)
+            SELECT page_id, day, COUNT(visitor_id), SUM(visitor_hits)
+            FROM page_visitors
+            GROUP BY page_id, day
+            ON DUPLICATE KEY UPDATE
+                views = page_history.views + VALUES(views),
+                hits = page_history.hits + VALUES(hits)
+            ",
+        )
+        .execute(&mut transaction)
+        .await?;
+
         for page in views {
             let id: i32 = page.get(0);
             let views: i64 = page.get(1);
@@ -1,14 +1,19 @@
 use crate::database::*;
+use crate::error::{conflict, not_found};
 use crate::Error;
 use bb8::Pool;
 use bb8_postgres::{tokio_postgres::NoTls, PostgresConnectionManager};
+use chrono::{offset::Utc, DateTime};
 use crypto::digest::Digest;
 use crypto::sha3::Sha3;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
-use sqlx::{mysql, ConnectOptions, Executor, Row};
+use sqlx::{mysql, ConnectOptions, Executor, MySql, Row, Transaction};
+use std::collections::HashMap;
+use std::time::SystemTime;
 
 pub struct MariaDBDatabaseTools {
     db_pool: mysql::MySqlPool,
+    pool_max: u32,
 }
 
 impl MariaDBDatabaseTools {
@@ -38,8 +43,241 @@ impl MariaDBDatabaseTools {
                 .max_connections(pool_amount)
                 .connect_with(init_conn)
                 .await?,
+            pool_max: pool_amount,
         })
     }
+
+    // migrate
+    //
+    // Brings the schema from version `from` up to SCHEMA_VERSION, one step
+    // at a time. init runs this after creating the version 1 tables, so any
+    // new table should only ever be created here.
+    async fn migrate(transaction: &mut Transaction<'_, MySql>, from: i64) -> Result<(), Error> {
+        for ver in (from + 1)..=SCHEMA_VERSION {
+            log::info!("migrating schema to version {}", ver);
+            match ver {
+                2 => {
+                    log::info!("creating table api_tokens");
+                    sqlx::query(
+                        "
+                        CREATE TABLE api_tokens (
+                            token_id INT AUTO_INCREMENT PRIMARY KEY,
+                            token_name TEXT NOT NULL,
+                            token_hash CHAR(64) NOT NULL UNIQUE,
+                            scopes JSON NOT NULL,
+                            created TIMESTAMP NOT NULL,
+                            last_used TIMESTAMP NULL
+                        )
+                        ",
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                3 => {
+                    log::info!("creating table sessions");
+                    sqlx::query(
+                        "
+                        CREATE TABLE sessions (
+                            session_hash CHAR(64) PRIMARY KEY,
+                            user_name TEXT NOT NULL,
+                            created TIMESTAMP NOT NULL,
+                            last_seen TIMESTAMP NOT NULL,
+                            csrf_token CHAR(44) NOT NULL
+                        )
+                        ",
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                4 => {
+                    log::info!("creating table audit_log");
+                    sqlx::query(
+                        "
+                        CREATE TABLE audit_log (
+                            audit_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+                            logged_at TIMESTAMP NOT NULL,
+                            actor TEXT NOT NULL,
+                            action VARCHAR(32) NOT NULL,
+                            target TEXT,
+                            before_value JSON,
+                            after_value JSON,
+                            source_ip VARCHAR(45)
+                        )
+                        ",
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                5 => {
+                    log::info!("creating tables page_history and page_history_monthly");
+                    sqlx::query(
+                        "
+                        CREATE TABLE page_history (
+                            page_id INT NOT NULL,
+                            day DATE NOT NULL,
+                            views BIGINT NOT NULL DEFAULT 0,
+                            hits BIGINT NOT NULL DEFAULT 0,
+                            PRIMARY KEY (page_id, day),
+                            FOREIGN KEY (page_id)
+                                REFERENCES pages (page_id)
+                                ON DELETE CASCADE
+                        )
+                        ",
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                    sqlx::query(
+                        "
+                        CREATE TABLE page_history_monthly (
+                            page_id INT NOT NULL,
+                            month DATE NOT NULL,
+                            views BIGINT NOT NULL DEFAULT 0,
+                            hits BIGINT NOT NULL DEFAULT 0,
+                            PRIMARY KEY (page_id, month),
+                            FOREIGN KEY (page_id)
+                                REFERENCES pages (page_id)
+                                ON DELETE CASCADE
+                        )
+                        ",
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                6 => {
+                    // unflushed visitors from before this can only be put on
+                    // the day of the upgrade
+                    log::info!("adding day to page_visitors");
+                    sqlx::query("ALTER TABLE page_visitors ADD COLUMN day DATE")
+                        .execute(&mut *transaction)
+                        .await?;
+                    sqlx::query("UPDATE page_visitors SET day = CURRENT_DATE")
+                        .execute(&mut *transaction)
+                        .await?;
+                    sqlx::query("ALTER TABLE page_visitors MODIFY day DATE NOT NULL")
+                        .execute(&mut *transaction)
+                        .await?;
+                }
+                _ => unreachable!("no migration step for schema version {}", ver),
+            }
+        }
+
+        sqlx::query("UPDATE settings SET setting = ? WHERE setting_name = 'schema_ver'")
+            .bind(&serde_json::to_value(SCHEMA_VERSION)?)
+            .execute(&mut *transaction)
+            .await?;
+
+        Ok(())
+    }
+
+    // The path of a folder, as used in the paths table: the name of every
+    // folder between it and the root, joined by slashes.
+    async fn folder_path(
+        transaction: &mut Transaction<'_, MySql>,
+        folder_id: i32,
+    ) -> Result<String, Error> {
+        let names = sqlx::query(
+            "
+            WITH RECURSIVE chain (folder_id, parent_id, folder_name, depth) AS (
+                SELECT folder_id, parent_id, folder_name, 0
+                FROM folders
+                WHERE folder_id = ?
+                UNION ALL
+                SELECT folders.folder_id, folders.parent_id, folders.folder_name, chain.depth + 1
+                FROM folders
+                INNER JOIN chain
+                ON folders.folder_id = chain.parent_id
+            )
+            SELECT folder_name
+            FROM chain
+            WHERE parent_id IS NOT NULL
+            ORDER BY depth DESC
+            ",
+        )
+        .bind(folder_id)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        if names.is_empty() && folder_id != 0 {
+            return not_found(format!("folder {} does not exist", folder_id));
+        }
+
+        Ok(names
+            .iter()
+            .map(|r| r.get::<String, usize>(0))
+            .collect::<Vec<String>>()
+            .join("/"))
+    }
+
+    fn child_path(parent: &str, name: &str) -> String {
+        match parent.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{}", parent, name),
+        }
+    }
+
+    // (page_id, path_id) of a page, if it exists
+    async fn find_page(
+        transaction: &mut Transaction<'_, MySql>,
+        folder_id: i32,
+        page_name: &str,
+    ) -> Result<Option<(i32, i32)>, Error> {
+        Ok(
+            sqlx::query("SELECT page_id, path_id FROM pages WHERE folder_id = ? AND page_name = ?")
+                .bind(folder_id)
+                .bind(page_name)
+                .fetch_optional(&mut *transaction)
+                .await?
+                .map(|r| (r.get(0), r.get(1))),
+        )
+    }
+
+    fn row_to_token(row: &mysql::MySqlRow) -> Result<ApiTokenRecord, Error> {
+        Ok(ApiTokenRecord {
+            id: row.get(0),
+            name: row.get(1),
+            scopes: serde_json::from_value(row.get(2))?,
+            created: row.get::<DateTime<Utc>, usize>(3).timestamp(),
+            last_used: row
+                .get::<Option<DateTime<Utc>>, usize>(4)
+                .map(|t| t.timestamp()),
+        })
+    }
+
+    // Every page with its path and totals, including visitors that haven't
+    // been flushed yet. The site tree and search both read from this.
+    const PAGE_TOTALS: &str = "
+        SELECT
+            pages.folder_id,
+            paths.path,
+            pages.page_name,
+            CAST(pages.total_views + COUNT(page_visitors.visitor_id) AS SIGNED) AS views,
+            CAST(pages.total_hits + COALESCE(SUM(page_visitors.visitor_hits), 0) AS SIGNED) AS hits,
+            pages.first_visited
+        FROM pages
+        INNER JOIN paths
+        ON paths.path_id = pages.path_id
+        LEFT JOIN page_visitors
+        ON page_visitors.page_id = pages.page_id
+        GROUP BY
+            pages.page_id, pages.folder_id, paths.path, pages.page_name,
+            pages.total_views, pages.total_hits, pages.first_visited
+    ";
+
+    // rows of (folder_id, path, page_name, views, hits, first_visited)
+    fn index_pages(rows: Vec<mysql::MySqlRow>) -> Vec<IndexPage> {
+        rows.iter()
+            .map(|r| IndexPage {
+                path: format!("/{}", r.get::<String, usize>(1)),
+                folder_id: r.get(0),
+                page: r.get(2),
+                views: r.get(3),
+                hits: r.get(4),
+                first_visited: r
+                    .get::<Option<DateTime<Utc>>, usize>(5)
+                    .map(|t| t.timestamp()),
+            })
+            .collect()
+    }
 }
 
 #[async_trait::async_trait]
@@ -61,11 +299,63 @@ impl DatabaseTool for MariaDBDatabaseTools {
         })
     }
 
+    async fn schema_version(&self) -> Result<Option<i64>, Error> {
+        let row =
+            match sqlx::query("SELECT setting FROM settings WHERE setting_name = 'schema_ver'")
+                .fetch_optional(&self.db_pool)
+                .await
+            {
+                Ok(r) => r,
+                // there's no settings table before init
+                Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42S02") => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        Ok(match row {
+            Some(r) => Some(serde_json::from_value(r.get(0))?),
+            None => None,
+        })
+    }
+
+    async fn close(&self) {
+        self.db_pool.close().await;
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        PoolStatus {
+            size: self.db_pool.size(),
+            idle: self.db_pool.num_idle() as u32,
+            max: self.pool_max,
+        }
+    }
+
+    async fn upgrade(&self) -> Result<(), Error> {
+        let ver: i64 = serde_json::from_value(
+            sqlx::query("SELECT setting FROM settings WHERE setting_name = 'schema_ver'")
+                .fetch_one(&self.db_pool)
+                .await?
+                .get(0),
+        )?;
+
+        if ver >= SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        log::info!("upgrading database from schema version {}", ver);
+        let mut transaction = self.db_pool.begin().await?;
+        Self::migrate(&mut transaction, ver).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
     async fn get_folder(&self, folder_id: i32) -> Result<FolderRecord, Error> {
         let mut pages: Vec<ViewRecord> = Vec::new();
         let mut folders: Vec<FolderRecordPartial> = Vec::new();
 
-        let folder = sqlx::query(
+        let folder = match sqlx::query(
             "
             SELECT folder_name, parent_id
             FROM folders
@@ -73,8 +363,12 @@ impl DatabaseTool for MariaDBDatabaseTools {
             ",
         )
         .bind(&folder_id)
-        .fetch_one(&self.db_pool)
-        .await?;
+        .fetch_optional(&self.db_pool)
+        .await?
+        {
+            Some(f) => f,
+            None => return not_found(format!("folder {} does not exist", folder_id)),
+        };
 
         let folder_name: String = folder.get(0);
         let folder_parent: Option<i32> = folder.get(1);
@@ -143,24 +437,72 @@ impl DatabaseTool for MariaDBDatabaseTools {
         .fetch_all(&self.db_pool)
         .await?;
 
+        // totals for this folder, and each folder in it, over everything
+        // under them
+        let totals = sqlx::query(
+            "
+            WITH RECURSIVE subtree (root_id, folder_id) AS (
+                SELECT folder_id, folder_id
+                FROM folders
+                WHERE folder_id = ? OR parent_id = ?
+                UNION ALL
+                SELECT subtree.root_id, folders.folder_id
+                FROM folders
+                INNER JOIN subtree
+                ON folders.parent_id = subtree.folder_id
+            )
+            SELECT
+                subtree.root_id,
+                CAST(COALESCE(SUM(page_totals.views), 0) AS SIGNED),
+                CAST(COALESCE(SUM(page_totals.hits), 0) AS SIGNED)
+            FROM subtree
+            LEFT JOIN (
+                SELECT
+                    pages.folder_id,
+                    pages.total_views + COUNT(page_visitors.visitor_id) AS views,
+                    pages.total_hits + COALESCE(SUM(page_visitors.visitor_hits), 0) AS hits
+                FROM pages
+                LEFT JOIN page_visitors
+                ON page_visitors.page_id = pages.page_id
+                GROUP BY pages.page_id, pages.folder_id, pages.total_views, pages.total_hits
+            ) AS page_totals
+            ON page_totals.folder_id = subtree.folder_id
+            GROUP BY subtree.root_id
+            ",
+        )
+        .bind(folder_id)
+        .bind(folder_id)
+        .fetch_all(&self.db_pool)
+        .await?
+        .iter()
+        .map(|r| (r.get(0), (r.get(1), r.get(2))))
+        .collect::<HashMap<i32, (i64, i64)>>();
+        let total = |id: i32| totals.get(&id).copied().unwrap_or((0, 0));
+
         for folder in folder_rows {
+            let (views, hits) = total(folder.get(0));
             folders.push(FolderRecordPartial {
                 id: folder.get(0),
                 name: folder.get(1),
+                views,
+                hits,
             });
         }
 
+        let (views, hits) = total(folder_id);
         Ok(FolderRecord {
             id: folder_id,
             parent_id: folder_parent,
             name: folder_name,
+            views,
+            hits,
             folders,
             pages,
         })
     }
 
     async fn get_page(&self, folder_id: i32, page_name: String) -> Result<PageRecord, Error> {
-        let page = sqlx::query(
+        let page = match sqlx::query(
             "
             SELECT path_id, page_id, folder_id
             FROM pages
@@ -169,8 +511,12 @@ impl DatabaseTool for MariaDBDatabaseTools {
         )
         .bind(&folder_id)
         .bind(&page_name)
-        .fetch_one(&self.db_pool)
-        .await?;
+        .fetch_optional(&self.db_pool)
+        .await?
+        {
+            Some(p) => p,
+            None => return not_found(format!("page {} does not exist", page_name)),
+        };
         let path_id: i32 = page.get(0);
 
         let page_views = sqlx::query(
@@ -196,6 +542,184 @@ impl DatabaseTool for MariaDBDatabaseTools {
         })
     }
 
+    async fn get_history(&self, folder_id: i32, page_name: String) -> Result<PageHistory, Error> {
+        let page_id: i32 =
+            match sqlx::query("SELECT page_id FROM pages WHERE folder_id = ? AND page_name = ?")
+                .bind(folder_id)
+                .bind(&page_name)
+                .fetch_optional(&self.db_pool)
+                .await?
+            {
+                Some(p) => p.get(0),
+                None => return not_found(format!("page {} does not exist", page_name)),
+            };
+
+        let records = |rows: Vec<mysql::MySqlRow>| -> Vec<HistoryRecord> {
+            rows.iter()
+                .map(|r| HistoryRecord {
+                    period: r.get(0),
+                    views: r.get(1),
+                    hits: r.get(2),
+                })
+                .collect()
+        };
+
+        let daily = sqlx::query(
+            "
+            SELECT DATE_FORMAT(day, '%Y-%m-%d'), views, hits
+            FROM page_history
+            WHERE page_id = ?
+            ORDER BY day
+            ",
+        )
+        .bind(page_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+        let monthly = sqlx::query(
+            "
+            SELECT DATE_FORMAT(month, '%Y-%m'), views, hits
+            FROM page_history_monthly
+            WHERE page_id = ?
+            ORDER BY month
+            ",
+        )
+        .bind(page_id)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(PageHistory {
+            daily: records(daily),
+            monthly: records(monthly),
+        })
+    }
+
+    // get_tree
+    //
+    // Two queries: every folder with the totals of everything under it,
+    // and the pages of every folder, numbered within their folder so each
+    // folder is limited on its own.
+    async fn get_tree(&self, query: IndexQuery) -> Result<TreeFolder, Error> {
+        let folders = sqlx::query(
+            format!(
+                "
+            WITH RECURSIVE subtree (root_id, folder_id) AS (
+                SELECT folder_id, folder_id
+                FROM folders
+                UNION ALL
+                SELECT subtree.root_id, folders.folder_id
+                FROM folders
+                INNER JOIN subtree
+                ON folders.parent_id = subtree.folder_id
+            )
+            SELECT
+                folders.folder_id,
+                folders.parent_id,
+                folders.folder_name,
+                CAST(COALESCE(SUM(page_totals.views), 0) AS SIGNED),
+                CAST(COALESCE(SUM(page_totals.hits), 0) AS SIGNED),
+                MIN(page_totals.first_visited),
+                (SELECT COUNT(*) FROM pages WHERE pages.folder_id = folders.folder_id)
+            FROM folders
+            INNER JOIN subtree
+            ON subtree.root_id = folders.folder_id
+            LEFT JOIN ({}) AS page_totals
+            ON page_totals.folder_id = subtree.folder_id
+            GROUP BY folders.folder_id, folders.parent_id, folders.folder_name
+            ",
+                Self::PAGE_TOTALS
+            )
+            .as_str(),
+        )
+        .fetch_all(&self.db_pool)
+        .await?
+        .iter()
+        .map(|r| TreeFolderRow {
+            parent_id: r.get(1),
+            folder: TreeFolder {
+                id: r.get(0),
+                name: r.get(2),
+                views: r.get(3),
+                hits: r.get(4),
+                first_visited: r
+                    .get::<Option<DateTime<Utc>>, usize>(5)
+                    .map(|t| t.timestamp()),
+                page_count: r.get(6),
+                folders: Vec::new(),
+                pages: Vec::new(),
+            },
+        })
+        .collect();
+
+        let pages = sqlx::query(
+            format!(
+                "
+            SELECT folder_id, path, page_name, views, hits, first_visited
+            FROM (
+                SELECT
+                    page_totals.*,
+                    ROW_NUMBER() OVER (PARTITION BY folder_id ORDER BY {}, path) AS n
+                FROM ({}) AS page_totals
+            ) AS numbered
+            WHERE n > ? AND n <= ? + ?
+            ORDER BY folder_id, n
+            ",
+                query.order.order_by(query.sort.column()),
+                Self::PAGE_TOTALS
+            )
+            .as_str(),
+        )
+        .bind(query.offset)
+        .bind(query.offset)
+        .bind(query.limit.min(IndexQuery::MAX_LIMIT))
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        TreeFolder::tree(folders, Self::index_pages(pages), &query)
+    }
+
+    async fn search(&self, query: SearchQuery) -> Result<SearchResults, Error> {
+        let pattern = query.pattern();
+
+        let total: i64 = sqlx::query(
+            "
+            SELECT COUNT(*)
+            FROM paths
+            INNER JOIN pages
+            ON pages.path_id = paths.path_id
+            WHERE LOWER(paths.path) LIKE ?
+            ",
+        )
+        .bind(&pattern)
+        .fetch_one(&self.db_pool)
+        .await?
+        .get(0);
+
+        let pages = sqlx::query(
+            format!(
+                "
+            SELECT folder_id, path, page_name, views, hits, first_visited
+            FROM ({}) AS page_totals
+            WHERE LOWER(path) LIKE ?
+            ORDER BY {}, path
+            LIMIT ? OFFSET ?
+            ",
+                Self::PAGE_TOTALS,
+                query.order.order_by(query.sort.column())
+            )
+            .as_str(),
+        )
+        .bind(&pattern)
+        .bind(query.limit.min(SearchQuery::MAX_LIMIT))
+        .bind(query.offset)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(SearchResults {
+            total,
+            pages: Self::index_pages(pages),
+        })
+    }
+
     // delete_folder
     //
     // Performs a cascading delete on a folder.
@@ -221,7 +745,7 @@ impl DatabaseTool for MariaDBDatabaseTools {
     //
     // Deletes a single page from the database.
     async fn delete_page(&self, folder_id: i32, page_name: String) -> Result<(), Error> {
-        let path_id: i32 = sqlx::query(
+        let path_id: i32 = match sqlx::query(
             "
             SELECT path_id
             FROM pages
@@ -230,9 +754,12 @@ impl DatabaseTool for MariaDBDatabaseTools {
         )
         .bind(&page_name)
         .bind(&folder_id)
-        .fetch_one(&self.db_pool)
+        .fetch_optional(&self.db_pool)
         .await?
-        .get(0);
+        {
+            Some(p) => p.get(0),
+            None => return not_found(format!("page {} does not exist", page_name)),
+        };
 
         sqlx::query(format!("DROP VIEW path_{}", path_id).as_str())
             .execute(&self.db_pool)
@@ -261,6 +788,295 @@ impl DatabaseTool for MariaDBDatabaseTools {
 
         Ok(())
     }
+    async fn move_page(
+        &self,
+        folder_id: i32,
+        page_name: String,
+        to_folder_id: i32,
+        to_name: String,
+    ) -> Result<(), Error> {
+        let mut transaction = self.db_pool.begin().await?;
+
+        let (page_id, path_id) =
+            match Self::find_page(&mut transaction, folder_id, &page_name).await? {
+                Some(p) => p,
+                None => return not_found(format!("page {} does not exist", page_name)),
+            };
+
+        if let Some((id, _)) = Self::find_page(&mut transaction, to_folder_id, &to_name).await? {
+            if id != page_id {
+                return conflict("a page already exists there - merge it instead");
+            }
+        }
+
+        let path = Self::child_path(
+            &Self::folder_path(&mut transaction, to_folder_id).await?,
+            &to_name,
+        );
+
+        sqlx::query("UPDATE pages SET folder_id = ?, page_name = ? WHERE page_id = ?")
+            .bind(to_folder_id)
+            .bind(&to_name)
+            .bind(page_id)
+            .execute(&mut transaction)
+            .await?;
+        sqlx::query("UPDATE paths SET path = ? WHERE path_id = ?")
+            .bind(&path)
+            .bind(path_id)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn move_folder(
+        &self,
+        folder_id: i32,
+        to_parent_id: i32,
+        to_name: String,
+    ) -> Result<(), Error> {
+        if folder_id == 0 {
+            return conflict("the root folder can't be moved");
+        }
+
+        let mut transaction = self.db_pool.begin().await?;
+
+        let folder =
+            match sqlx::query("SELECT parent_id, folder_name FROM folders WHERE folder_id = ?")
+                .bind(folder_id)
+                .fetch_optional(&mut transaction)
+                .await?
+            {
+                Some(f) => f,
+                None => return not_found(format!("folder {} does not exist", folder_id)),
+            };
+        let parent_id: i32 = folder.get(0);
+        let name: String = folder.get(1);
+
+        // every folder inside this one, and this one
+        let subtree: Vec<i32> = sqlx::query(
+            "
+            WITH RECURSIVE subtree (folder_id) AS (
+                SELECT folder_id
+                FROM folders
+                WHERE folder_id = ?
+                UNION ALL
+                SELECT folders.folder_id
+                FROM folders
+                INNER JOIN subtree
+                ON folders.parent_id = subtree.folder_id
+            )
+            SELECT folder_id FROM subtree
+            ",
+        )
+        .bind(folder_id)
+        .fetch_all(&mut transaction)
+        .await?
+        .iter()
+        .map(|r| r.get(0))
+        .collect();
+
+        if subtree.contains(&to_parent_id) {
+            return conflict("a folder can't be moved inside itself");
+        }
+
+        if sqlx::query(
+            "
+            SELECT folder_id
+            FROM folders
+            WHERE parent_id = ? AND folder_name = ? AND folder_id <> ?
+            ",
+        )
+        .bind(to_parent_id)
+        .bind(&to_name)
+        .bind(folder_id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .is_some()
+        {
+            return conflict("a folder already exists there");
+        }
+
+        let self_page = Self::find_page(&mut transaction, parent_id, &name).await?;
+        if let Some((id, _)) = Self::find_page(&mut transaction, to_parent_id, &to_name).await? {
+            if self_page.map(|(p, _)| p) != Some(id) {
+                return conflict("a page with the folder's new name already exists there");
+            }
+        }
+
+        let old_path = Self::folder_path(&mut transaction, folder_id).await?;
+        let new_path = Self::child_path(
+            &Self::folder_path(&mut transaction, to_parent_id).await?,
+            &to_name,
+        );
+
+        sqlx::query("UPDATE folders SET parent_id = ?, folder_name = ? WHERE folder_id = ?")
+            .bind(to_parent_id)
+            .bind(&to_name)
+            .bind(folder_id)
+            .execute(&mut transaction)
+            .await?;
+
+        for id in subtree {
+            sqlx::query(
+                "
+                UPDATE paths
+                INNER JOIN pages
+                ON paths.path_id = pages.path_id
+                SET paths.path = CONCAT(?, SUBSTRING(paths.path, CHAR_LENGTH(?) + 1))
+                WHERE pages.folder_id = ?
+                ",
+            )
+            .bind(&new_path)
+            .bind(&old_path)
+            .bind(id)
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        if let Some((page_id, path_id)) = self_page {
+            sqlx::query("UPDATE pages SET folder_id = ?, page_name = ? WHERE page_id = ?")
+                .bind(to_parent_id)
+                .bind(&to_name)
+                .bind(page_id)
+                .execute(&mut transaction)
+                .await?;
+            sqlx::query("UPDATE paths SET path = ? WHERE path_id = ?")
+                .bind(&new_path)
+                .bind(path_id)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn merge_page(
+        &self,
+        folder_id: i32,
+        page_name: String,
+        into_folder_id: i32,
+        into_name: String,
+    ) -> Result<(), Error> {
+        let mut transaction = self.db_pool.begin().await?;
+
+        let (from_id, from_path_id) =
+            match Self::find_page(&mut transaction, folder_id, &page_name).await? {
+                Some(p) => p,
+                None => return not_found(format!("page {} does not exist", page_name)),
+            };
+        let (into_id, _) =
+            match Self::find_page(&mut transaction, into_folder_id, &into_name).await? {
+                Some(p) => p,
+                None => return not_found(format!("page {} does not exist", into_name)),
+            };
+
+        if from_id == into_id {
+            return conflict("a page can't be merged into itself");
+        }
+
+        // unflushed visitors of both pages are counted once, with their
+        // hits added together
+        sqlx::query(
+            "
+            UPDATE page_visitors AS into_page
+            INNER JOIN page_visitors AS from_page
+            ON from_page.visitor_id = into_page.visitor_id
+            SET into_page.visitor_hits = into_page.visitor_hits + from_page.visitor_hits
+            WHERE from_page.page_id = ? AND into_page.page_id = ?
+            ",
+        )
+        .bind(from_id)
+        .bind(into_id)
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(
+            "
+            DELETE from_page
+            FROM page_visitors AS from_page
+            INNER JOIN page_visitors AS into_page
+            ON from_page.visitor_id = into_page.visitor_id
+            WHERE from_page.page_id = ? AND into_page.page_id = ?
+            ",
+        )
+        .bind(from_id)
+        .bind(into_id)
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query("UPDATE page_visitors SET page_id = ? WHERE page_id = ?")
+            .bind(into_id)
+            .bind(from_id)
+            .execute(&mut transaction)
+            .await?;
+
+        // history is kept, and added together on days (and months) that
+        // both pages have
+        for (table, period) in &[("page_history", "day"), ("page_history_monthly", "month")] {
+            sqlx::query(
+                format!(
+                    "
+                INSERT INTO {0} (page_id, {1}, views, hits)
+                SELECT ?, h.{1}, h.views, h.hits
+                FROM {0} AS h
+                WHERE h.page_id = ?
+                ON DUPLICATE KEY UPDATE
+                    views = {0}.views + VALUES(views),
+                    hits = {0}.hits + VALUES(hits)
+                ",
+                    table, period
+                )
+                .as_str(),
+            )
+            .bind(into_id)
+            .bind(from_id)
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        sqlx::query(
+            "
+            UPDATE pages AS into_page
+            INNER JOIN pages AS from_page
+            ON from_page.page_id = ?
+            SET
+                into_page.total_views = into_page.total_views + from_page.total_views,
+                into_page.total_hits = into_page.total_hits + from_page.total_hits,
+                into_page.first_visited = COALESCE(
+                    LEAST(into_page.first_visited, from_page.first_visited),
+                    into_page.first_visited,
+                    from_page.first_visited
+                )
+            WHERE into_page.page_id = ?
+            ",
+        )
+        .bind(from_id)
+        .bind(into_id)
+        .execute(&mut transaction)
+        .await?;
+
+        sqlx::query("DELETE FROM pages WHERE page_id = ?")
+            .bind(from_id)
+            .execute(&mut transaction)
+            .await?;
+        sqlx::query("DELETE FROM paths WHERE path_id = ?")
+            .bind(from_path_id)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        // DDL commits implicitly in MariaDB, so the view is only dropped
+        // once everything else has gone through
+        sqlx::query(format!("DROP VIEW path_{}", from_path_id).as_str())
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(())
+    }
 
     async fn get_settings(&self) -> Result<DenViewSettings, Error> {
         match self.check().await {
@@ -337,6 +1153,57 @@ impl DatabaseTool for MariaDBDatabaseTools {
         })
     }
 
+    async fn check_password(&self, pass: String) -> Result<bool, Error> {
+        let mut hasher = Sha3::sha3_256();
+        hasher.input_str(&pass);
+        let hashed_pass = hasher.result_str();
+
+        let db_pass: String = serde_json::from_value(
+            sqlx::query(
+                "
+                SELECT setting
+                FROM settings
+                WHERE setting_name = 'password'
+                ",
+            )
+            .fetch_one(&self.db_pool)
+            .await?
+            .get(0),
+        )?;
+
+        Ok(hashed_pass == db_pass)
+    }
+
+    async fn set_credentials(&self, user: Option<String>, pass: String) -> Result<(), Error> {
+        let mut transaction = self.db_pool.begin().await?;
+
+        if let Some(user) = user {
+            sqlx::query("UPDATE settings SET setting = ? WHERE setting_name = 'user'")
+                .bind(&serde_json::to_value(&user)?)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        let mut hasher = Sha3::sha3_256();
+        hasher.input_str(&pass);
+        let hashed_pass = hasher.result_str();
+
+        sqlx::query("UPDATE settings SET setting = ? WHERE setting_name = 'password'")
+            .bind(&serde_json::to_value(&hashed_pass)?)
+            .execute(&mut transaction)
+            .await?;
+
+        sqlx::query("DELETE FROM sessions")
+            .execute(&mut transaction)
+            .await?;
+        sqlx::query("DELETE FROM api_tokens")
+            .execute(&mut transaction)
+            .await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
     async fn init(&self, init: DenViewInit) -> Result<(), Error> {
         log::info!("!!! CREATING DATABASE NOW !!!");
         let mut transaction = self.db_pool.begin().await?;
@@ -495,10 +1362,230 @@ impl DatabaseTool for MariaDBDatabaseTools {
             .execute(&mut transaction)
             .await?;
 
+        Self::migrate(&mut transaction, 1).await?;
+
         log::info!("committing to database");
         transaction.commit().await?;
 
         log::info!("!!! DATABASE CREATION COMPLETE !!!");
         Ok(())
     }
+
+    async fn get_tokens(&self) -> Result<Vec<ApiTokenRecord>, Error> {
+        sqlx::query(
+            "
+            SELECT token_id, token_name, scopes, created, last_used
+            FROM api_tokens
+            ORDER BY token_id
+            ",
+        )
+        .fetch_all(&self.db_pool)
+        .await?
+        .iter()
+        .map(Self::row_to_token)
+        .collect()
+    }
+
+    async fn create_token(
+        &self,
+        token: ApiTokenRequest,
+        token_hash: String,
+    ) -> Result<ApiTokenRecord, Error> {
+        let row = sqlx::query(
+            "
+            INSERT INTO
+                api_tokens (token_name, token_hash, scopes, created)
+            VALUES
+                (?, ?, ?, ?)
+            RETURNING
+                token_id, token_name, scopes, created, last_used
+            ",
+        )
+        .bind(&token.name)
+        .bind(&token_hash)
+        .bind(&serde_json::to_value(&token.scopes)?)
+        .bind(DateTime::<Utc>::from(SystemTime::now()))
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        Self::row_to_token(&row)
+    }
+
+    async fn delete_token(&self, token_id: i32) -> Result<(), Error> {
+        sqlx::query("DELETE FROM api_tokens WHERE token_id = ?")
+            .bind(token_id)
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // MariaDB has no UPDATE ... RETURNING, so this is two queries
+    // instead of the one that Postgres gets away with.
+    async fn check_token(&self, token_hash: String) -> Result<Option<ApiTokenRecord>, Error> {
+        sqlx::query("UPDATE api_tokens SET last_used = ? WHERE token_hash = ?")
+            .bind(DateTime::<Utc>::from(SystemTime::now()))
+            .bind(&token_hash)
+            .execute(&self.db_pool)
+            .await?;
+
+        sqlx::query(
+            "
+            SELECT token_id, token_name, scopes, created, last_used
+            FROM api_tokens
+            WHERE token_hash = ?
+            ",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&self.db_pool)
+        .await?
+        .map(|r| Self::row_to_token(&r))
+        .transpose()
+    }
+
+    async fn create_session(
+        &self,
+        session_hash: String,
+        user: String,
+        csrf_token: String,
+    ) -> Result<(), Error> {
+        let now = DateTime::<Utc>::from(SystemTime::now());
+
+        sqlx::query(
+            "
+            INSERT INTO
+                sessions (session_hash, user_name, created, last_seen, csrf_token)
+            VALUES
+                (?, ?, ?, ?, ?)
+            ",
+        )
+        .bind(&session_hash)
+        .bind(&user)
+        .bind(now)
+        .bind(now)
+        .bind(&csrf_token)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn check_session(
+        &self,
+        session_hash: String,
+        idle_timeout: u64,
+        max_age: u64,
+    ) -> Result<Option<SessionRecord>, Error> {
+        sqlx::query("DELETE FROM sessions WHERE last_seen < ? OR created < ?")
+            .bind(DateTime::<Utc>::from(util::time_before(idle_timeout)))
+            .bind(DateTime::<Utc>::from(util::time_before(max_age)))
+            .execute(&self.db_pool)
+            .await?;
+
+        sqlx::query("UPDATE sessions SET last_seen = ? WHERE session_hash = ?")
+            .bind(DateTime::<Utc>::from(SystemTime::now()))
+            .bind(&session_hash)
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(sqlx::query(
+            "
+            SELECT user_name, created, last_seen, csrf_token
+            FROM sessions
+            WHERE session_hash = ?
+            ",
+        )
+        .bind(&session_hash)
+        .fetch_optional(&self.db_pool)
+        .await?
+        .map(|r| SessionRecord {
+            user: r.get(0),
+            created: r.get::<DateTime<Utc>, usize>(1).timestamp(),
+            last_seen: r.get::<DateTime<Utc>, usize>(2).timestamp(),
+            csrf_token: r.get(3),
+        }))
+    }
+
+    async fn delete_session(&self, session_hash: String) -> Result<(), Error> {
+        sqlx::query("DELETE FROM sessions WHERE session_hash = ?")
+            .bind(&session_hash)
+            .execute(&self.db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), Error> {
+        sqlx::query(
+            "
+            INSERT INTO
+                audit_log (logged_at, actor, action, target, before_value, after_value, source_ip)
+            VALUES
+                (?, ?, ?, ?, ?, ?, ?)
+            ",
+        )
+        .bind(DateTime::<Utc>::from(SystemTime::now()))
+        .bind(&entry.actor)
+        .bind(entry.action.as_str())
+        .bind(&entry.target)
+        .bind(&entry.before)
+        .bind(&entry.after)
+        .bind(&entry.source_ip)
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_audit(&self, query: AuditQuery) -> Result<Vec<AuditRecord>, Error> {
+        let since = query
+            .since
+            .map(|t| DateTime::<Utc>::from(util::from_unix_time(t)));
+        let until = query
+            .until
+            .map(|t| DateTime::<Utc>::from(util::from_unix_time(t)));
+
+        let rows = sqlx::query(
+            "
+            SELECT
+                audit_id, logged_at, actor, action, target, before_value, after_value, source_ip
+            FROM audit_log
+            WHERE (? IS NULL OR actor = ?)
+                AND (? IS NULL OR action = ?)
+                AND (? IS NULL OR target = ?)
+                AND (? IS NULL OR logged_at >= ?)
+                AND (? IS NULL OR logged_at <= ?)
+            ORDER BY audit_id DESC
+            LIMIT ? OFFSET ?
+            ",
+        )
+        .bind(&query.actor)
+        .bind(&query.actor)
+        .bind(&query.action)
+        .bind(&query.action)
+        .bind(&query.target)
+        .bind(&query.target)
+        .bind(since)
+        .bind(since)
+        .bind(until)
+        .bind(until)
+        .bind(query.limit.min(AuditQuery::MAX_LIMIT))
+        .bind(query.offset)
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| AuditRecord {
+                id: r.get(0),
+                time: r.get::<DateTime<Utc>, usize>(1).timestamp(),
+                actor: r.get(2),
+                action: r.get(3),
+                target: r.get(4),
+                before: r.get(5),
+                after: r.get(6),
+                source_ip: r.get(7),
+            })
+            .collect())
+    }
 }
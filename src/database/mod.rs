@@ -1,12 +1,29 @@
 pub mod mariadb;
 pub mod postgres;
 mod start;
-mod util;
+pub mod util;
 
 pub use self::start::start_db;
+use crate::error::ApiError;
 use crate::Error;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+// The schema version that init creates, and that DatabaseTool::upgrade
+// brings older databases up to. Bump this whenever a migration step is
+// added to the backends.
+pub const SCHEMA_VERSION: i64 = 6;
 
 // COMMON STRUCTS
+
+// How many connections a pool has open, and how many of those are idle.
+#[derive(serde::Serialize, Clone, Copy, Debug)]
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
 #[derive(serde::Serialize)]
 pub struct ViewRecord {
     pub page: String,
@@ -24,10 +41,30 @@ pub struct PageRecord {
     pub hits: i64,
 }
 
+// Views and hits over one day (YYYY-MM-DD) or month (YYYY-MM).
+#[derive(serde::Serialize)]
+pub struct HistoryRecord {
+    pub period: String,
+    pub views: i64,
+    pub hits: i64,
+}
+
+// A page's history, oldest first: days that haven't been rolled up yet,
+// and the months that older days were rolled up into.
+#[derive(serde::Serialize)]
+pub struct PageHistory {
+    pub daily: Vec<HistoryRecord>,
+    pub monthly: Vec<HistoryRecord>,
+}
+
+// Folder views and hits are the totals of every page in the folder, and
+// in every folder under it.
 #[derive(serde::Serialize)]
 pub struct FolderRecordPartial {
     pub id: i32,
     pub name: String,
+    pub views: i64,
+    pub hits: i64,
 }
 
 #[derive(serde::Serialize)]
@@ -35,17 +72,487 @@ pub struct FolderRecord {
     pub id: i32,
     pub parent_id: Option<i32>,
     pub name: String,
+    pub views: i64,
+    pub hits: i64,
     pub folders: Vec<FolderRecordPartial>,
     pub pages: Vec<ViewRecord>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum TokenScope {
+    #[serde(rename = "read:stats")]
+    ReadStats,
+    #[serde(rename = "write:flush")]
+    WriteFlush,
+    #[serde(rename = "read:metrics")]
+    ReadMetrics,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ApiTokenRecord {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ApiTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<TokenScope>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SessionRecord {
+    pub user: String,
+    pub created: i64,
+    pub last_seen: i64,
+    pub csrf_token: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum AuditAction {
+    Init,
+    UpdateSettings,
+    DeletePage,
+    DeleteFolder,
+    MovePage,
+    MoveFolder,
+    MergePage,
+    Flush,
+    Login,
+    LoginFailed,
+    UpdateCredentials,
+    CreateToken,
+    DeleteToken,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Init => "init",
+            AuditAction::UpdateSettings => "settings.update",
+            AuditAction::DeletePage => "page.delete",
+            AuditAction::DeleteFolder => "folder.delete",
+            AuditAction::MovePage => "page.move",
+            AuditAction::MoveFolder => "folder.move",
+            AuditAction::MergePage => "page.merge",
+            AuditAction::Flush => "flush",
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login.failed",
+            AuditAction::UpdateCredentials => "credentials.update",
+            AuditAction::CreateToken => "token.create",
+            AuditAction::DeleteToken => "token.delete",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub actor: String,
+    pub action: AuditAction,
+    pub target: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub source_ip: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(actor: String, action: AuditAction, source_ip: std::net::IpAddr) -> Self {
+        AuditEntry {
+            actor,
+            action,
+            target: None,
+            before: None,
+            after: None,
+            source_ip: Some(source_ip.to_string()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub time: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub source_ip: Option<String>,
+}
+
+// Filters for reading the audit log. Everything left out matches
+// anything; since/until are unix timestamps.
+#[derive(serde::Deserialize, Debug)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    #[serde(default = "AuditQuery::default_limit")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+impl AuditQuery {
+    pub const MAX_LIMIT: u32 = 1000;
+
+    fn default_limit() -> u32 {
+        100
+    }
+}
+
+// What the site tree and page search are ordered by. Folders in the tree
+// are ordered by the totals of everything under them, and by the first
+// visit to any page under them.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexSort {
+    Views,
+    Hits,
+    FirstVisited,
+}
+
+impl IndexSort {
+    // The column this sorts by, in the page totals that both the tree and
+    // search read from.
+    pub fn column(&self) -> &'static str {
+        match self {
+            IndexSort::Views => "views",
+            IndexSort::Hits => "hits",
+            IndexSort::FirstVisited => "first_visited",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    // An ORDER BY for a column. Pages that were never visited have no
+    // first visit, and always go last.
+    pub fn order_by(&self, column: &str) -> String {
+        match self {
+            SortOrder::Asc => format!("({0} IS NULL), {0} ASC", column),
+            SortOrder::Desc => format!("({0} IS NULL), {0} DESC", column),
+        }
+    }
+}
+
+// Ordering and paging for the site tree. The limit and offset apply to
+// the pages of each folder on their own; folders are never left out.
+#[derive(serde::Deserialize, Debug)]
+#[serde(default)]
+pub struct IndexQuery {
+    pub sort: IndexSort,
+    pub order: SortOrder,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl Default for IndexQuery {
+    fn default() -> Self {
+        IndexQuery {
+            sort: IndexSort::Views,
+            order: SortOrder::Desc,
+            limit: 100,
+            offset: 0,
+        }
+    }
+}
+
+impl IndexQuery {
+    pub const MAX_LIMIT: u32 = 1000;
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMatch {
+    Substring,
+    Prefix,
+}
+
+// A search of full page paths, ignoring case. A leading slash in q is
+// ignored, since paths are stored without one.
+#[derive(serde::Deserialize, Debug)]
+#[serde(default)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(rename = "match")]
+    pub mode: SearchMatch,
+    pub sort: IndexSort,
+    pub order: SortOrder,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        SearchQuery {
+            q: String::new(),
+            mode: SearchMatch::Substring,
+            sort: IndexSort::Views,
+            order: SortOrder::Desc,
+            limit: 100,
+            offset: 0,
+        }
+    }
+}
+
+impl SearchQuery {
+    pub const MAX_LIMIT: u32 = 1000;
+
+    // The LIKE pattern for q, with LIKE's own wildcards escaped so they
+    // only match themselves.
+    pub fn pattern(&self) -> String {
+        let q = self
+            .q
+            .trim_start_matches('/')
+            .to_lowercase()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+
+        match self.mode {
+            SearchMatch::Substring => format!("%{}%", q),
+            SearchMatch::Prefix => format!("{}%", q),
+        }
+    }
+}
+
+// A page in the site tree or in search results. The path starts with a
+// slash, like the paths that are tracked; first_visited is a unix
+// timestamp, and is missing for pages that were never visited.
+#[derive(serde::Serialize)]
+pub struct IndexPage {
+    pub path: String,
+    pub folder_id: i32,
+    pub page: String,
+    pub views: i64,
+    pub hits: i64,
+    pub first_visited: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchResults {
+    pub total: i64,
+    pub pages: Vec<IndexPage>,
+}
+
+// A folder in the site tree. Views, hits and first_visited cover
+// everything under the folder, like FolderRecord; page_count is only the
+// pages directly in it, including any left out by the limit.
+#[derive(serde::Serialize)]
+pub struct TreeFolder {
+    pub id: i32,
+    pub name: String,
+    pub views: i64,
+    pub hits: i64,
+    pub first_visited: Option<i64>,
+    pub page_count: i64,
+    pub folders: Vec<TreeFolder>,
+    pub pages: Vec<IndexPage>,
+}
+
+// One row of a backend's folder query: the folder, and its parent.
+pub struct TreeFolderRow {
+    pub parent_id: Option<i32>,
+    pub folder: TreeFolder,
+}
+
+impl TreeFolder {
+    // tree
+    //
+    // Builds the site tree from every folder, and the pages (already
+    // sorted and limited) in each of them. The root folder is the one
+    // without a parent. Folders whose parent never turns up, which the
+    // schema shouldn't allow, are put under the root instead of being
+    // left out.
+    pub fn tree(
+        rows: Vec<TreeFolderRow>,
+        pages: Vec<IndexPage>,
+        query: &IndexQuery,
+    ) -> Result<TreeFolder, Error> {
+        let mut children: HashMap<i32, Vec<TreeFolder>> = HashMap::new();
+        let mut root = None;
+        let mut pages_in: HashMap<i32, Vec<IndexPage>> = HashMap::new();
+        for page in pages {
+            pages_in.entry(page.folder_id).or_default().push(page);
+        }
+
+        let mut ids = HashSet::new();
+        for mut row in rows {
+            ids.insert(row.folder.id);
+            row.folder.pages = pages_in.remove(&row.folder.id).unwrap_or_default();
+            match row.parent_id {
+                None => root = Some(row.folder),
+                Some(parent) => children.entry(parent).or_default().push(row.folder),
+            }
+        }
+
+        match root {
+            None => Err(Box::new(ApiError::Internal(
+                "root folder is missing".into(),
+            ))),
+            Some(mut root) => {
+                TreeFolder::attach(&mut root, &mut children, query);
+
+                // parents that are missing go first, so that what's under
+                // them stays there; anything left after that is folders
+                // that are each other's parents
+                let mut orphaned = children.keys().copied().collect::<Vec<i32>>();
+                orphaned.sort_by_key(|parent| ids.contains(parent));
+                for parent in orphaned {
+                    for mut folder in children.remove(&parent).unwrap_or_default() {
+                        log::error!("folder {} is not under the root folder", folder.id);
+                        TreeFolder::attach(&mut folder, &mut children, query);
+                        root.folders.push(folder);
+                    }
+                }
+                TreeFolder::sort(&mut root.folders, query);
+
+                Ok(root)
+            }
+        }
+    }
+
+    fn attach(
+        folder: &mut TreeFolder,
+        children: &mut HashMap<i32, Vec<TreeFolder>>,
+        query: &IndexQuery,
+    ) {
+        let mut folders = children.remove(&folder.id).unwrap_or_default();
+        for child in folders.iter_mut() {
+            TreeFolder::attach(child, children, query);
+        }
+
+        TreeFolder::sort(&mut folders, query);
+        folder.folders = folders;
+    }
+
+    fn sort(folders: &mut [TreeFolder], query: &IndexQuery) {
+        folders.sort_by(|a, b| {
+            let (ord, unvisited) = match query.sort {
+                IndexSort::Views => (a.views.cmp(&b.views), Ordering::Equal),
+                IndexSort::Hits => (a.hits.cmp(&b.hits), Ordering::Equal),
+                // folders that were never visited go last either way,
+                // like pages
+                IndexSort::FirstVisited => (
+                    a.first_visited.cmp(&b.first_visited),
+                    a.first_visited.is_none().cmp(&b.first_visited.is_none()),
+                ),
+            };
+            let ord = match query.order {
+                SortOrder::Asc => ord,
+                SortOrder::Desc => ord.reverse(),
+            };
+            unvisited.then(ord).then_with(|| a.name.cmp(&b.name))
+        });
+    }
+}
+
+// How a path is checked before it's tracked: by requesting it from the
+// tracked site, by the path rules and sitemap, or by both.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SiteCheck {
+    Probe,
+    Rules,
+    Both,
+}
+
+// What to do with hits from visitors that send `DNT: 1` or `Sec-GPC: 1`:
+// nothing different, not count them at all, or count them as a hit without
+// storing anything about who the visitor is.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivacySignals {
+    Ignore,
+    Skip,
+    Anonymous,
+}
+
+// What a visitor is told apart by: their address and User-Agent, the same
+// with only the /24 (IPv4) or /48 (IPv6) of their address, only their
+// User-Agent, or nothing, in which case every hit is anonymous (see
+// PrivacySignals) and views are never counted.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum VisitorIdentity {
+    Ip,
+    TruncatedIp,
+    UserAgent,
+    None,
+}
+
+// Any setting added after the first release has to be covered by
+// Default, since older databases won't have it stored yet.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+#[serde(default)]
 pub struct DenViewSettings {
     pub site: String,
     pub use_https: bool,
     pub ignore_queries: bool,
     pub remove_index_pages: bool,
     pub always_auth_locally: bool,
+    // Dashboard sessions, in seconds: how long a session can go unused,
+    // and how long it can live at all.
+    pub session_idle_timeout: u64,
+    pub session_max_age: u64,
+    // How often to check the database for settings changed by another
+    // instance, in seconds. Zero turns this off, except on Lambda, where
+    // it means checking on every invocation.
+    pub settings_poll_interval: u64,
+    // Origins allowed to read the tracking routes cross-origin. Empty
+    // means only the tracked site itself.
+    pub cors_origins: Vec<String>,
+    // Path normalization (see servers::routing::normalize): the steps to
+    // run in order, rewrite rules, and the query parameters to keep.
+    pub path_normalization: Vec<String>,
+    pub path_rewrites: Vec<String>,
+    pub query_params: Vec<String>,
+    // Hits to leave uncounted (see servers::routing::exclude).
+    pub exclude_paths: Vec<String>,
+    pub exclude_queries: Vec<String>,
+    pub exclude_ips: Vec<String>,
+    pub site_check: SiteCheck,
+    // Path allowlist rules (see servers::routing::rules), and a sitemap
+    // file or URL to allow paths from, reloaded every `sitemap_refresh`
+    // seconds.
+    pub path_rules: Vec<String>,
+    pub sitemap: String,
+    pub sitemap_refresh: u64,
+    // Outbound requests to the tracked site, in seconds, and how long
+    // probe results are cached for (see servers::routing::probe).
+    pub check_connect_timeout: u64,
+    pub check_read_timeout: u64,
+    pub check_cache_ttl: u64,
+    pub check_cache_negative_ttl: u64,
+    pub check_cache_size: usize,
+    // On shutdown, how long in-flight requests get to finish, in seconds,
+    // and whether to flush visitors before exiting.
+    pub shutdown_timeout: u64,
+    pub flush_on_shutdown: bool,
+    // How long badges can be cached for, in seconds.
+    pub badge_max_age: u64,
+    pub privacy_signals: PrivacySignals,
+    // How long daily history is kept, in days, before it's rolled up into
+    // monthly totals, and how long those are kept, in months. Zero keeps
+    // them forever.
+    pub daily_history_retention: u64,
+    pub monthly_history_retention: u64,
+    pub visitor_identity: VisitorIdentity,
 }
 
 impl Default for DenViewSettings {
@@ -56,6 +563,32 @@ impl Default for DenViewSettings {
             ignore_queries: true,
             remove_index_pages: true,
             always_auth_locally: false,
+            session_idle_timeout: 30 * 60,
+            session_max_age: 12 * 60 * 60,
+            settings_poll_interval: 0,
+            cors_origins: Vec::new(),
+            path_normalization: vec!["strip_trailing_slash".into()],
+            path_rewrites: Vec::new(),
+            query_params: Vec::new(),
+            exclude_paths: Vec::new(),
+            exclude_queries: Vec::new(),
+            exclude_ips: Vec::new(),
+            site_check: SiteCheck::Probe,
+            path_rules: Vec::new(),
+            sitemap: String::new(),
+            sitemap_refresh: 60 * 60,
+            check_connect_timeout: 5,
+            check_read_timeout: 10,
+            check_cache_ttl: 10 * 60,
+            check_cache_negative_ttl: 60,
+            check_cache_size: 10000,
+            shutdown_timeout: 30,
+            flush_on_shutdown: false,
+            badge_max_age: 5 * 60,
+            privacy_signals: PrivacySignals::Ignore,
+            daily_history_retention: 90,
+            monthly_history_retention: 24,
+            visitor_identity: VisitorIdentity::Ip,
         }
     }
 }
@@ -68,11 +601,12 @@ impl From<DenViewInit> for DenViewSettings {
             ignore_queries: init.ignore_queries,
             remove_index_pages: init.remove_index_pages,
             always_auth_locally: init.always_auth_locally,
+            ..Default::default()
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct DenViewInit {
     pub site: String,
     pub use_https: bool,
@@ -102,6 +636,23 @@ pub trait Database {
     async fn execute(&self, op: &DatabaseOperation<'_>) -> Result<Option<ViewRecord>, Error>;
 
     async fn get_settings(&self) -> Result<DenViewSettings, Error>;
+
+    // The views of every path given, in one query. Paths that were never
+    // tracked are left out, rather than being an error.
+    async fn get_views(&self, paths: &[String]) -> Result<Vec<ViewRecord>, Error>;
+
+    fn pool_status(&self) -> PoolStatus;
+
+    // Fails if a connection can't be had, or can't run a query.
+    async fn ping(&self) -> Result<(), Error>;
+
+    // Closes the pool's connections. Nothing can use the pool after this.
+    async fn close(&self);
+
+    // Rolls daily history older than `daily_days` up into monthly totals,
+    // and deletes monthly totals older than `monthly_months`, not counting
+    // the current month. Zero skips either.
+    async fn purge(&self, daily_days: u64, monthly_months: u64) -> Result<(), Error>;
 }
 
 #[derive(Debug)]
@@ -116,8 +667,9 @@ pub enum DatabaseOperation<'a> {
     // Records should be tested for correctness before calling it
     // into the database.
     //
-    // This will, as of v0.1, only increment views.
-    UpdatePage(&'a str, &'a str),
+    // This will, as of v0.1, only increment views. Without visitor info,
+    // only a hit is counted, and no visitor is stored.
+    UpdatePage(&'a str, Option<&'a str>),
 
     /*
     // CREATE: Creates a new page in the database.
@@ -139,6 +691,8 @@ pub enum DatabaseOperation<'a> {
     // compared to fetching/updating, this should only be done by authorized
     // clients/callers in order to ensure that the database is not overloaded
     // with concurrent transactions.
+    //
+    // What was flushed is also added to the current day's history.
     Flush,
 }
 
@@ -146,19 +700,265 @@ pub enum DatabaseOperation<'a> {
 pub trait DatabaseTool {
     async fn check(&self) -> Result<bool, Error>;
 
+    // The stored schema_ver, or None if the database hasn't been
+    // initialized. Unlike check, this fails if the database can't be
+    // reached, instead of saying that it isn't initialized.
+    async fn schema_version(&self) -> Result<Option<i64>, Error>;
+
+    fn pool_status(&self) -> PoolStatus;
+
+    // Closes the pool's connections. Nothing can use the pool after this.
+    async fn close(&self);
+
+    // Applies any migrations between the stored schema_ver and
+    // SCHEMA_VERSION. Only valid on an initialized database.
+    async fn upgrade(&self) -> Result<(), Error>;
+
     async fn get_folder(&self, folder_id: i32) -> Result<FolderRecord, Error>;
 
     async fn get_page(&self, folder_id: i32, page_name: String) -> Result<PageRecord, Error>;
 
+    async fn get_history(&self, folder_id: i32, page_name: String) -> Result<PageHistory, Error>;
+
+    // Every folder, with the pages in each, from the root folder down.
+    async fn get_tree(&self, query: IndexQuery) -> Result<TreeFolder, Error>;
+
+    async fn search(&self, query: SearchQuery) -> Result<SearchResults, Error>;
+
     async fn delete_folder(&self, folder_id: i32) -> Result<(), Error>;
 
     async fn delete_page(&self, folder_id: i32, page_name: String) -> Result<(), Error>;
 
+    // Moves a page into another folder, renames it, or both, keeping all
+    // of its views. Fails with a conflict if a page is already there.
+    async fn move_page(
+        &self,
+        folder_id: i32,
+        page_name: String,
+        to_folder_id: i32,
+        to_name: String,
+    ) -> Result<(), Error>;
+
+    // Moves a folder under another parent, renames it, or both, along
+    // with everything inside it and the page of the same name beside it.
+    async fn move_folder(
+        &self,
+        folder_id: i32,
+        to_parent_id: i32,
+        to_name: String,
+    ) -> Result<(), Error>;
+
+    // Adds one page's views and hits into another's, and removes it.
+    async fn merge_page(
+        &self,
+        folder_id: i32,
+        page_name: String,
+        into_folder_id: i32,
+        into_name: String,
+    ) -> Result<(), Error>;
+
     async fn get_settings(&self) -> Result<DenViewSettings, Error>;
 
     async fn update_settings(&self, settinsg: DenViewSettings) -> Result<(), Error>;
 
     async fn auth(&self, user: String, pass: String) -> Result<bool, Error>;
 
+    async fn check_password(&self, pass: String) -> Result<bool, Error>;
+
+    // Replaces the dashboard password, and the user if one is given.
+    // Every session and API token is revoked along with the old password.
+    async fn set_credentials(&self, user: Option<String>, pass: String) -> Result<(), Error>;
+
     async fn init(&self, init: DenViewInit) -> Result<(), Error>;
+
+    async fn get_tokens(&self) -> Result<Vec<ApiTokenRecord>, Error>;
+
+    // Stores a new token by its hash - the plaintext token is never
+    // written to the database.
+    async fn create_token(
+        &self,
+        token: ApiTokenRequest,
+        token_hash: String,
+    ) -> Result<ApiTokenRecord, Error>;
+
+    async fn delete_token(&self, token_id: i32) -> Result<(), Error>;
+
+    // Looks up a token by its hash, marking it as used if it exists.
+    async fn check_token(&self, token_hash: String) -> Result<Option<ApiTokenRecord>, Error>;
+
+    async fn create_session(
+        &self,
+        session_hash: String,
+        user: String,
+        csrf_token: String,
+    ) -> Result<(), Error>;
+
+    // Looks up a session by its hash and refreshes its idle timer.
+    // Expired sessions are removed before the lookup happens, so
+    // anything returned here is still valid.
+    async fn check_session(
+        &self,
+        session_hash: String,
+        idle_timeout: u64,
+        max_age: u64,
+    ) -> Result<Option<SessionRecord>, Error>;
+
+    async fn delete_session(&self, session_hash: String) -> Result<(), Error>;
+
+    // The audit log is append-only: there is intentionally no way
+    // to change or remove an entry once it's been written.
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), Error>;
+
+    async fn get_audit(&self, query: AuditQuery) -> Result<Vec<AuditRecord>, Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(id: i32, parent: Option<i32>, views: i64) -> TreeFolderRow {
+        TreeFolderRow {
+            parent_id: parent,
+            folder: TreeFolder {
+                id,
+                name: format!("f{}", id),
+                views,
+                hits: views,
+                first_visited: None,
+                page_count: 0,
+                folders: Vec::new(),
+                pages: Vec::new(),
+            },
+        }
+    }
+
+    fn page(folder_id: i32, name: &str) -> IndexPage {
+        IndexPage {
+            path: format!("/{}", name),
+            folder_id,
+            page: name.into(),
+            views: 0,
+            hits: 0,
+            first_visited: None,
+        }
+    }
+
+    fn ids(folders: &[TreeFolder]) -> Vec<i32> {
+        folders.iter().map(|f| f.id).collect()
+    }
+
+    #[test]
+    fn test_tree() {
+        let rows = vec![
+            folder(3, Some(1), 1),
+            folder(1, Some(0), 5),
+            folder(0, None, 16),
+            folder(2, Some(0), 10),
+        ];
+        let pages = vec![page(0, "about"), page(3, "deep"), page(3, "deeper")];
+        let tree = TreeFolder::tree(rows, pages, &IndexQuery::default()).unwrap();
+
+        assert_eq!(tree.id, 0);
+        assert_eq!(ids(&tree.folders), vec![2, 1]);
+        assert_eq!(tree.pages.len(), 1);
+        assert_eq!(ids(&tree.folders[1].folders), vec![3]);
+        assert_eq!(tree.folders[1].folders[0].pages.len(), 2);
+        assert!(tree.folders[0].folders.is_empty());
+
+        let query = IndexQuery {
+            order: SortOrder::Asc,
+            ..IndexQuery::default()
+        };
+        let rows = vec![
+            folder(0, None, 0),
+            folder(2, Some(0), 10),
+            folder(1, Some(0), 5),
+        ];
+        let tree = TreeFolder::tree(rows, Vec::new(), &query).unwrap();
+        assert_eq!(ids(&tree.folders), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_tree_first_visited() {
+        let tree = |order| {
+            let mut rows = vec![
+                folder(0, None, 0),
+                folder(1, Some(0), 0),
+                folder(2, Some(0), 0),
+                folder(3, Some(0), 0),
+            ];
+            rows[2].folder.first_visited = Some(200);
+            rows[3].folder.first_visited = Some(100);
+
+            let query = IndexQuery {
+                sort: IndexSort::FirstVisited,
+                order,
+                ..IndexQuery::default()
+            };
+            ids(&TreeFolder::tree(rows, Vec::new(), &query).unwrap().folders)
+        };
+
+        // never visited goes last, whichever way it's sorted
+        assert_eq!(tree(SortOrder::Asc), vec![3, 2, 1]);
+        assert_eq!(tree(SortOrder::Desc), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn test_tree_orphans() {
+        let rows = vec![
+            folder(0, None, 0),
+            folder(1, Some(0), 3),
+            // a parent that isn't there, with a child of its own
+            folder(4, Some(99), 2),
+            folder(5, Some(4), 1),
+            // parents of each other
+            folder(6, Some(7), 1),
+            folder(7, Some(6), 1),
+        ];
+        let tree = TreeFolder::tree(rows, vec![page(5, "kept")], &IndexQuery::default()).unwrap();
+
+        let top = ids(&tree.folders);
+        assert_eq!(top[..2], [1, 4]);
+        assert_eq!(top.len(), 3);
+        assert_eq!(ids(&tree.folders[1].folders), vec![5]);
+        assert_eq!(tree.folders[1].folders[0].pages.len(), 1);
+
+        // either one can end up on top, with the other inside it
+        let cycle = &tree.folders[2];
+        let mut both = vec![cycle.id, cycle.folders[0].id];
+        both.sort_unstable();
+        assert_eq!(both, vec![6, 7]);
+        assert_eq!(cycle.folders.len(), 1);
+    }
+
+    #[test]
+    fn test_tree_without_root() {
+        let rows = vec![folder(1, Some(0), 0)];
+        assert!(TreeFolder::tree(rows, Vec::new(), &IndexQuery::default()).is_err());
+    }
+
+    #[test]
+    fn test_search_pattern() {
+        let search = |q: &str, mode| {
+            SearchQuery {
+                q: q.into(),
+                mode,
+                ..SearchQuery::default()
+            }
+            .pattern()
+        };
+
+        assert_eq!(search("Blog/Post", SearchMatch::Substring), "%blog/post%");
+        assert_eq!(search("/blog", SearchMatch::Prefix), "blog%");
+        assert_eq!(search("//blog", SearchMatch::Prefix), "blog%");
+        assert_eq!(search("blog/", SearchMatch::Prefix), "blog/%");
+        assert_eq!(search("", SearchMatch::Substring), "%%");
+        assert_eq!(search("", SearchMatch::Prefix), "%");
+
+        // LIKE's wildcards, and its escape, only match themselves
+        assert_eq!(search("50%", SearchMatch::Prefix), "50\\%%");
+        assert_eq!(search("a_b", SearchMatch::Substring), "%a\\_b%");
+        assert_eq!(search("a\\b", SearchMatch::Substring), "%a\\\\b%");
+        assert_eq!(search("\\%", SearchMatch::Prefix), "\\\\\\%%");
+    }
 }
@@ -8,19 +8,19 @@
 
 use crate::database::util;
 use crate::database::{Database, DatabaseOperation};
-use crate::database::{DenViewSettings, ViewRecord};
+use crate::database::{DenViewSettings, PoolStatus, ViewRecord};
+use crate::error;
 use crate::Error;
 use bb8::Pool;
 use bb8_postgres::{
     tokio_postgres::{config::Config as PostgresConfig, NoTls, Row},
     PostgresConnectionManager,
 };
-use crypto::digest::Digest;
-use crypto::sha3::Sha3;
 use std::time::SystemTime;
 
 pub struct Postgres {
     db_pool: Pool<PostgresConnectionManager<NoTls>>,
+    pool_max: u32,
 }
 
 impl Postgres {
@@ -39,6 +39,7 @@ impl Postgres {
                 .max_size(pool_amount)
                 .build(PostgresConnectionManager::new(config, NoTls))
                 .await?,
+            pool_max: pool_amount,
         })
     }
 }
@@ -49,7 +50,7 @@ impl Database for Postgres {
         match op {
             DatabaseOperation::Get(path) => Ok(Some(self.get_page_info(path).await?)),
             DatabaseOperation::UpdatePage(path, info) => {
-                self.append_visitor(path, info).await?;
+                self.append_visitor(path, *info).await?;
                 Ok(None)
             }
             /*
@@ -70,6 +71,121 @@ impl Database for Postgres {
         }
     }
 
+    async fn ping(&self) -> Result<(), Error> {
+        self.db_pool.get().await?.simple_query("SELECT 1").await?;
+
+        Ok(())
+    }
+
+    // bb8 has no way to close a pool while it's still shared: its
+    // connections are closed when the last handle to it is dropped, so
+    // this only makes sure nothing is still waiting on one.
+    async fn close(&self) {
+        let state = self.db_pool.state();
+        if state.connections > state.idle_connections {
+            log::warn!(
+                "closing with {} database connections still in use",
+                state.connections - state.idle_connections
+            );
+        }
+    }
+
+    async fn get_views(&self, paths: &[String]) -> Result<Vec<ViewRecord>, Error> {
+        let conn = self.db_pool.get().await?;
+
+        // the same sums as the path_ views, for every path at once
+        Ok(conn
+            .query(
+                "
+            SELECT
+                paths.path,
+                pages.total_views + (
+                    SELECT COUNT(visitor_id)
+                    FROM page_visitors
+                    WHERE page_visitors.page_id = pages.page_id
+                ),
+                pages.total_hits + COALESCE((
+                    SELECT SUM(visitor_hits)
+                    FROM page_visitors
+                    WHERE page_visitors.page_id = pages.page_id
+                ), 0)
+            FROM paths
+            INNER JOIN pages
+            ON pages.path_id = paths.path_id
+            WHERE paths.path = ANY($1)
+            ",
+                &[&paths],
+            )
+            .await?
+            .iter()
+            .map(|r| ViewRecord {
+                page: r.get(0),
+                views: r.get(1),
+                hits: r.get(2),
+            })
+            .collect())
+    }
+
+    async fn purge(&self, daily_days: u64, monthly_months: u64) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        if daily_days > 0 {
+            let days = daily_days.min(i32::MAX as u64) as i32;
+            transaction
+                .execute(
+                    "
+                INSERT INTO page_history_monthly (page_id, month, views, hits)
+                SELECT page_id, date_trunc('month', day)::date, SUM(views), SUM(hits)
+                FROM page_history
+                WHERE day < CURRENT_DATE - $1::int
+                GROUP BY page_id, date_trunc('month', day)
+                ON CONFLICT (page_id, month) DO UPDATE
+                SET
+                    views = page_history_monthly.views + EXCLUDED.views,
+                    hits = page_history_monthly.hits + EXCLUDED.hits
+                ",
+                    &[&days],
+                )
+                .await?;
+            transaction
+                .execute(
+                    "DELETE FROM page_history WHERE day < CURRENT_DATE - $1::int",
+                    &[&days],
+                )
+                .await?;
+            log::info!("rolled up daily history older than {} days", daily_days);
+        }
+
+        if monthly_months > 0 {
+            let months = monthly_months.min(i32::MAX as u64) as i32;
+            let deleted = transaction
+                .execute(
+                    "
+                DELETE FROM page_history_monthly
+                WHERE month < date_trunc('month', CURRENT_DATE) - make_interval(months => $1)
+                ",
+                    &[&months],
+                )
+                .await?;
+            log::info!("deleted {} expired monthly totals", deleted);
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        let state = self.db_pool.state();
+
+        PoolStatus {
+            size: state.connections,
+            idle: state.idle_connections,
+            max: self.pool_max,
+        }
+    }
+
     async fn get_settings(&self) -> Result<DenViewSettings, Error> {
         let conn = self.db_pool.get().await?;
 
@@ -97,10 +213,13 @@ impl Postgres {
         // this should ONLY RETURN a single row, ALWAYS
         // any other result is completely wrong
 
-        let path_id: i32 = conn
-            .query_one("SELECT path_id FROM paths WHERE path = $1", &[&path])
+        let path_id: i32 = match conn
+            .query_opt("SELECT path_id FROM paths WHERE path = $1", &[&path])
             .await?
-            .get(0);
+        {
+            Some(row) => row.get(0),
+            None => return error::not_found(format!("{} has not been tracked", path)),
+        };
 
         // This is only safe because of the path_id abstraction that occurs.
         // This should not be replicated in any other circumstance.
@@ -118,7 +237,7 @@ impl Postgres {
         })
     }
 
-    async fn append_visitor(&self, path: &str, visitor_info: &str) -> Result<(), Error> {
+    async fn append_visitor(&self, path: &str, visitor_info: Option<&str>) -> Result<(), Error> {
         log::debug!("recording new visitor");
         let conn = self.db_pool.get().await?;
 
@@ -140,11 +259,26 @@ impl Postgres {
             .unwrap_or(self.create_page(path).await?)
             .get(0);
 
-        let mut hasher = Sha3::sha3_256();
+        // anonymous hits are counted in hit_count, but never in view_count,
+        // since that only counts visitor IDs
+        //
+        // every row is put on the day it was added, which is the day it's
+        // flushed into the page's history under; a visitor's later hits go
+        // on the same row, and so the same day, as their view
+        let visitor_info = match visitor_info {
+            Some(v) => v,
+            None => {
+                conn.execute(
+                    "INSERT INTO page_visitors (visitor_id, page_id, day) VALUES (NULL, $1, CURRENT_DATE)",
+                    &[&page_id],
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
         let salt: String = conn.query_one("SELECT salt FROM salt", &[]).await?.get(0);
-        hasher.input_str(&(visitor_info.to_string() + &salt));
-        let visitor_hash = hasher.result_str();
-        println!("{}", visitor_hash);
+        let visitor_hash = util::visitor_hash(&salt, visitor_info);
 
         // optional! this is because if the visitor doesn't already exist, it is instead
         // added into the visitors table
@@ -184,7 +318,7 @@ impl Postgres {
                 .await?;
             } else {
                 conn.execute(
-                    "INSERT INTO page_visitors (visitor_id, page_id) VALUES ($1, $2)",
+                    "INSERT INTO page_visitors (visitor_id, page_id, day) VALUES ($1, $2, CURRENT_DATE)",
                     &[&id, &page_id],
                 )
                 .await?;
@@ -196,7 +330,7 @@ impl Postgres {
             )
             .await?;
             conn.execute(
-                "INSERT INTO page_visitors (visitor_id, page_id) VALUES ($1, $2)",
+                "INSERT INTO page_visitors (visitor_id, page_id, day) VALUES ($1, $2, CURRENT_DATE)",
                 &[&visitor_hash, &page_id],
             )
             .await?;
@@ -331,9 +465,31 @@ impl Postgres {
     async fn flush(&self) -> Result<(), Error> {
         log::info!("flushing page_visitors to database now...");
         let mut conn = self.db_pool.get().await?;
-
-        let views = conn.query("SELECT * FROM total_views", &[]).await?;
         let transaction = conn.transaction().await?;
+
+        // hits wait until the flush is done, so the totals, the history and
+        // what's deleted all come from the same rows; reads carry on
+        transaction
+            .execute("LOCK TABLE page_visitors IN SHARE ROW EXCLUSIVE MODE", &[])
+            .await?;
+        let views = transaction.query("SELECT * FROM total_views", &[]).await?;
+
+        transaction
+            .execute(
+                "
+            INSERT INTO page_history (page_id, day, views, hits)
+            SELECT page_id, day, COUNT(visitor_id), SUM(visitor_hits)
+            FROM page_visitors
+            GROUP BY page_id, day
+            ON CONFLICT (page_id, day) DO UPDATE
+            SET
+                views = page_history.views + EXCLUDED.views,
+                hits = page_history.hits + EXCLUDED.hits
+            ",
+                &[],
+            )
+            .await?;
+
         for page in views {
             let id: i32 = page.get(0);
             let views: i64 = page.get(1);
@@ -1,12 +1,19 @@
 use crate::database::*;
+use crate::error::{conflict, not_found};
 use crate::Error;
 use bb8::Pool;
-use bb8_postgres::{tokio_postgres::NoTls, PostgresConnectionManager};
+use bb8_postgres::{
+    tokio_postgres::{error::SqlState, NoTls, Row, Transaction},
+    PostgresConnectionManager,
+};
 use crypto::digest::Digest;
 use crypto::sha3::Sha3;
+use std::collections::HashMap;
+use std::time::SystemTime;
 
 pub struct PostgresDatabaseTools {
     db_pool: Pool<PostgresConnectionManager<NoTls>>,
+    pool_max: u32,
 }
 
 impl PostgresDatabaseTools {
@@ -25,8 +32,241 @@ impl PostgresDatabaseTools {
                 .max_size(pool_amount) // just in case - maybe make this configurable later?
                 .build(PostgresConnectionManager::new(config, NoTls))
                 .await?,
+            pool_max: pool_amount,
         })
     }
+
+    // migrate
+    //
+    // Brings the schema from version `from` up to SCHEMA_VERSION, one step
+    // at a time. init runs this after creating the version 1 tables, so any
+    // new table should only ever be created here.
+    async fn migrate(transaction: &Transaction<'_>, from: i64) -> Result<(), Error> {
+        for ver in (from + 1)..=SCHEMA_VERSION {
+            log::info!("migrating schema to version {}", ver);
+            match ver {
+                2 => {
+                    log::info!("creating table api_tokens");
+                    transaction
+                        .execute(
+                            "
+                        CREATE TABLE api_tokens (
+                            token_id INT PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY,
+                            token_name TEXT NOT NULL,
+                            token_hash TEXT NOT NULL UNIQUE,
+                            scopes JSON NOT NULL,
+                            created TIMESTAMP NOT NULL,
+                            last_used TIMESTAMP
+                        )
+                        ",
+                            &[],
+                        )
+                        .await?;
+                }
+                3 => {
+                    log::info!("creating table sessions");
+                    transaction
+                        .execute(
+                            "
+                        CREATE TABLE sessions (
+                            session_hash TEXT PRIMARY KEY,
+                            user_name TEXT NOT NULL,
+                            created TIMESTAMP NOT NULL,
+                            last_seen TIMESTAMP NOT NULL,
+                            csrf_token TEXT NOT NULL
+                        )
+                        ",
+                            &[],
+                        )
+                        .await?;
+                }
+                4 => {
+                    log::info!("creating table audit_log");
+                    transaction
+                        .execute(
+                            "
+                        CREATE TABLE audit_log (
+                            audit_id BIGINT PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY,
+                            logged_at TIMESTAMP NOT NULL,
+                            actor TEXT NOT NULL,
+                            action TEXT NOT NULL,
+                            target TEXT,
+                            before_value JSON,
+                            after_value JSON,
+                            source_ip TEXT
+                        )
+                        ",
+                            &[],
+                        )
+                        .await?;
+                }
+                5 => {
+                    log::info!("creating tables page_history and page_history_monthly");
+                    transaction
+                        .execute(
+                            "
+                        CREATE TABLE page_history (
+                            page_id INT NOT NULL
+                                REFERENCES pages
+                                ON DELETE CASCADE,
+                            day DATE NOT NULL,
+                            views BIGINT NOT NULL DEFAULT 0,
+                            hits BIGINT NOT NULL DEFAULT 0,
+                            PRIMARY KEY (page_id, day)
+                        )
+                        ",
+                            &[],
+                        )
+                        .await?;
+                    transaction
+                        .execute(
+                            "
+                        CREATE TABLE page_history_monthly (
+                            page_id INT NOT NULL
+                                REFERENCES pages
+                                ON DELETE CASCADE,
+                            month DATE NOT NULL,
+                            views BIGINT NOT NULL DEFAULT 0,
+                            hits BIGINT NOT NULL DEFAULT 0,
+                            PRIMARY KEY (page_id, month)
+                        )
+                        ",
+                            &[],
+                        )
+                        .await?;
+                }
+                6 => {
+                    // unflushed visitors from before this can only be put on
+                    // the day of the upgrade
+                    log::info!("adding day to page_visitors");
+                    transaction
+                        .execute("ALTER TABLE page_visitors ADD COLUMN day DATE", &[])
+                        .await?;
+                    transaction
+                        .execute("UPDATE page_visitors SET day = CURRENT_DATE", &[])
+                        .await?;
+                    transaction
+                        .execute(
+                            "ALTER TABLE page_visitors ALTER COLUMN day SET NOT NULL",
+                            &[],
+                        )
+                        .await?;
+                }
+                _ => unreachable!("no migration step for schema version {}", ver),
+            }
+        }
+
+        transaction
+            .execute(
+                "UPDATE settings SET setting = $1 WHERE setting_name = 'schema_ver'",
+                &[&serde_json::to_value(SCHEMA_VERSION)?],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // The path of a folder, as used in the paths table: the name of every
+    // folder between it and the root, joined by slashes.
+    async fn folder_path(transaction: &Transaction<'_>, folder_id: i32) -> Result<String, Error> {
+        let names = transaction
+            .query(
+                "
+                WITH RECURSIVE chain (folder_id, parent_id, folder_name, depth) AS (
+                    SELECT folder_id, parent_id, folder_name, 0
+                    FROM folders
+                    WHERE folder_id = $1
+                    UNION ALL
+                    SELECT folders.folder_id, folders.parent_id, folders.folder_name, chain.depth + 1
+                    FROM folders
+                    INNER JOIN chain
+                    ON folders.folder_id = chain.parent_id
+                )
+                SELECT folder_name
+                FROM chain
+                WHERE parent_id IS NOT NULL
+                ORDER BY depth DESC
+                ",
+                &[&folder_id],
+            )
+            .await?;
+
+        if names.is_empty() && folder_id != 0 {
+            return not_found(format!("folder {} does not exist", folder_id));
+        }
+
+        Ok(names
+            .iter()
+            .map(|r| r.get::<usize, String>(0))
+            .collect::<Vec<String>>()
+            .join("/"))
+    }
+
+    fn child_path(parent: &str, name: &str) -> String {
+        match parent.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{}", parent, name),
+        }
+    }
+
+    // (page_id, path_id) of a page, if it exists
+    async fn find_page(
+        transaction: &Transaction<'_>,
+        folder_id: i32,
+        page_name: &str,
+    ) -> Result<Option<(i32, i32)>, Error> {
+        Ok(transaction
+            .query_opt(
+                "SELECT page_id, path_id FROM pages WHERE folder_id = $1 AND page_name = $2",
+                &[&folder_id, &page_name],
+            )
+            .await?
+            .map(|r| (r.get(0), r.get(1))))
+    }
+
+    fn row_to_token(row: &Row) -> Result<ApiTokenRecord, Error> {
+        Ok(ApiTokenRecord {
+            id: row.get(0),
+            name: row.get(1),
+            scopes: serde_json::from_value(row.get(2))?,
+            created: util::unix_time(row.get(3)),
+            last_used: row.get::<usize, Option<SystemTime>>(4).map(util::unix_time),
+        })
+    }
+
+    // Every page with its path and totals, including visitors that haven't
+    // been flushed yet. The site tree and search both read from this.
+    const PAGE_TOTALS: &str = "
+        SELECT
+            pages.folder_id,
+            paths.path,
+            pages.page_name,
+            pages.total_views + COUNT(page_visitors.visitor_id) AS views,
+            CAST(pages.total_hits + COALESCE(SUM(page_visitors.visitor_hits), 0) AS BIGINT) AS hits,
+            pages.first_visited
+        FROM pages
+        INNER JOIN paths
+        ON paths.path_id = pages.path_id
+        LEFT JOIN page_visitors
+        ON page_visitors.page_id = pages.page_id
+        GROUP BY
+            pages.page_id, pages.folder_id, paths.path, pages.page_name,
+            pages.total_views, pages.total_hits, pages.first_visited
+    ";
+
+    // rows of (folder_id, path, page_name, views, hits, first_visited)
+    fn index_pages(rows: Vec<Row>) -> Vec<IndexPage> {
+        rows.iter()
+            .map(|r| IndexPage {
+                path: format!("/{}", r.get::<usize, String>(1)),
+                folder_id: r.get(0),
+                page: r.get(2),
+                views: r.get(3),
+                hits: r.get(4),
+                first_visited: r.get::<usize, Option<SystemTime>>(5).map(util::unix_time),
+            })
+            .collect()
+    }
 }
 
 #[async_trait::async_trait]
@@ -52,14 +292,81 @@ impl DatabaseTool for PostgresDatabaseTools {
         })
     }
 
+    async fn schema_version(&self) -> Result<Option<i64>, Error> {
+        let conn = self.db_pool.get().await?;
+
+        let row = match conn
+            .query_opt(
+                "SELECT setting FROM settings WHERE setting_name = 'schema_ver'",
+                &[],
+            )
+            .await
+        {
+            Ok(r) => r,
+            // there's no settings table before init
+            Err(e) if e.code() == Some(&SqlState::UNDEFINED_TABLE) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(match row {
+            Some(r) => Some(serde_json::from_value(r.get(0))?),
+            None => None,
+        })
+    }
+
+    // See Postgres::close.
+    async fn close(&self) {
+        let state = self.db_pool.state();
+        if state.connections > state.idle_connections {
+            log::warn!(
+                "closing with {} database connections still in use",
+                state.connections - state.idle_connections
+            );
+        }
+    }
+
+    fn pool_status(&self) -> PoolStatus {
+        let state = self.db_pool.state();
+
+        PoolStatus {
+            size: state.connections,
+            idle: state.idle_connections,
+            max: self.pool_max,
+        }
+    }
+
+    async fn upgrade(&self) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().await?;
+
+        let ver: i64 = serde_json::from_value(
+            conn.query_one(
+                "SELECT setting FROM settings WHERE setting_name = 'schema_ver'",
+                &[],
+            )
+            .await?
+            .get(0),
+        )?;
+
+        if ver >= SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        log::info!("upgrading database from schema version {}", ver);
+        let transaction = conn.transaction().await?;
+        Self::migrate(&transaction, ver).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
     async fn get_folder(&self, folder_id: i32) -> Result<FolderRecord, Error> {
         let conn = self.db_pool.get().await?;
 
         let mut pages: Vec<ViewRecord> = Vec::new();
         let mut folders: Vec<FolderRecordPartial> = Vec::new();
 
-        let folder = conn
-            .query_one(
+        let folder = match conn
+            .query_opt(
                 "
             SELECT folder_name, parent_id
             FROM folders
@@ -67,7 +374,11 @@ impl DatabaseTool for PostgresDatabaseTools {
             ",
                 &[&folder_id],
             )
-            .await?;
+            .await?
+        {
+            Some(f) => f,
+            None => return not_found(format!("folder {} does not exist", folder_id)),
+        };
 
         let folder_name: String = folder.get(0);
         let folder_parent: Option<i32> = folder.get(1);
@@ -136,17 +447,64 @@ impl DatabaseTool for PostgresDatabaseTools {
             )
             .await?;
 
+        // totals for this folder, and each folder in it, over everything
+        // under them
+        let totals = conn
+            .query(
+                "
+            WITH RECURSIVE subtree (root_id, folder_id) AS (
+                SELECT folder_id, folder_id
+                FROM folders
+                WHERE folder_id = $1 OR parent_id = $1
+                UNION ALL
+                SELECT subtree.root_id, folders.folder_id
+                FROM folders
+                INNER JOIN subtree
+                ON folders.parent_id = subtree.folder_id
+            )
+            SELECT
+                subtree.root_id,
+                CAST(COALESCE(SUM(page_totals.views), 0) AS BIGINT),
+                CAST(COALESCE(SUM(page_totals.hits), 0) AS BIGINT)
+            FROM subtree
+            LEFT JOIN (
+                SELECT
+                    pages.folder_id,
+                    pages.total_views + COUNT(page_visitors.visitor_id) AS views,
+                    pages.total_hits + COALESCE(SUM(page_visitors.visitor_hits), 0) AS hits
+                FROM pages
+                LEFT JOIN page_visitors
+                ON page_visitors.page_id = pages.page_id
+                GROUP BY pages.page_id, pages.folder_id, pages.total_views, pages.total_hits
+            ) AS page_totals
+            ON page_totals.folder_id = subtree.folder_id
+            GROUP BY subtree.root_id
+            ",
+                &[&folder_id],
+            )
+            .await?
+            .iter()
+            .map(|r| (r.get(0), (r.get(1), r.get(2))))
+            .collect::<HashMap<i32, (i64, i64)>>();
+        let total = |id: i32| totals.get(&id).copied().unwrap_or((0, 0));
+
         for folder in folder_rows {
+            let (views, hits) = total(folder.get(0));
             folders.push(FolderRecordPartial {
                 id: folder.get(0),
                 name: folder.get(1),
+                views,
+                hits,
             });
         }
 
+        let (views, hits) = total(folder_id);
         Ok(FolderRecord {
             id: folder_id,
             parent_id: folder_parent,
             name: folder_name,
+            views,
+            hits,
             folders,
             pages,
         })
@@ -155,8 +513,8 @@ impl DatabaseTool for PostgresDatabaseTools {
     async fn get_page(&self, folder_id: i32, page_name: String) -> Result<PageRecord, Error> {
         let conn = self.db_pool.get().await?;
 
-        let page = conn
-            .query_one(
+        let page = match conn
+            .query_opt(
                 "
             SELECT path_id, page_id, folder_id
             FROM pages
@@ -164,7 +522,11 @@ impl DatabaseTool for PostgresDatabaseTools {
             ",
                 &[&folder_id, &page_name],
             )
-            .await?;
+            .await?
+        {
+            Some(p) => p,
+            None => return not_found(format!("page {} does not exist", page_name)),
+        };
         let path_id: i32 = page.get(0);
 
         let page_views = conn
@@ -191,6 +553,191 @@ impl DatabaseTool for PostgresDatabaseTools {
         })
     }
 
+    async fn get_history(&self, folder_id: i32, page_name: String) -> Result<PageHistory, Error> {
+        let conn = self.db_pool.get().await?;
+
+        let page_id: i32 = match conn
+            .query_opt(
+                "SELECT page_id FROM pages WHERE folder_id = $1 AND page_name = $2",
+                &[&folder_id, &page_name],
+            )
+            .await?
+        {
+            Some(p) => p.get(0),
+            None => return not_found(format!("page {} does not exist", page_name)),
+        };
+
+        let records = |rows: Vec<Row>| -> Vec<HistoryRecord> {
+            rows.iter()
+                .map(|r| HistoryRecord {
+                    period: r.get(0),
+                    views: r.get(1),
+                    hits: r.get(2),
+                })
+                .collect()
+        };
+
+        let daily = conn
+            .query(
+                "
+            SELECT to_char(day, 'YYYY-MM-DD'), views, hits
+            FROM page_history
+            WHERE page_id = $1
+            ORDER BY day
+            ",
+                &[&page_id],
+            )
+            .await?;
+        let monthly = conn
+            .query(
+                "
+            SELECT to_char(month, 'YYYY-MM'), views, hits
+            FROM page_history_monthly
+            WHERE page_id = $1
+            ORDER BY month
+            ",
+                &[&page_id],
+            )
+            .await?;
+
+        Ok(PageHistory {
+            daily: records(daily),
+            monthly: records(monthly),
+        })
+    }
+
+    // get_tree
+    //
+    // Two queries: every folder with the totals of everything under it,
+    // and the pages of every folder, numbered within their folder so each
+    // folder is limited on its own.
+    async fn get_tree(&self, query: IndexQuery) -> Result<TreeFolder, Error> {
+        let conn = self.db_pool.get().await?;
+
+        let folders = conn
+            .query(
+                format!(
+                    "
+            WITH RECURSIVE subtree (root_id, folder_id) AS (
+                SELECT folder_id, folder_id
+                FROM folders
+                UNION ALL
+                SELECT subtree.root_id, folders.folder_id
+                FROM folders
+                INNER JOIN subtree
+                ON folders.parent_id = subtree.folder_id
+            )
+            SELECT
+                folders.folder_id,
+                folders.parent_id,
+                folders.folder_name,
+                CAST(COALESCE(SUM(page_totals.views), 0) AS BIGINT),
+                CAST(COALESCE(SUM(page_totals.hits), 0) AS BIGINT),
+                MIN(page_totals.first_visited),
+                (SELECT COUNT(*) FROM pages WHERE pages.folder_id = folders.folder_id)
+            FROM folders
+            INNER JOIN subtree
+            ON subtree.root_id = folders.folder_id
+            LEFT JOIN ({}) AS page_totals
+            ON page_totals.folder_id = subtree.folder_id
+            GROUP BY folders.folder_id, folders.parent_id, folders.folder_name
+            ",
+                    Self::PAGE_TOTALS
+                )
+                .as_str(),
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|r| TreeFolderRow {
+                parent_id: r.get(1),
+                folder: TreeFolder {
+                    id: r.get(0),
+                    name: r.get(2),
+                    views: r.get(3),
+                    hits: r.get(4),
+                    first_visited: r.get::<usize, Option<SystemTime>>(5).map(util::unix_time),
+                    page_count: r.get(6),
+                    folders: Vec::new(),
+                    pages: Vec::new(),
+                },
+            })
+            .collect();
+
+        let pages = conn
+            .query(
+                format!(
+                    "
+            SELECT folder_id, path, page_name, views, hits, first_visited
+            FROM (
+                SELECT
+                    page_totals.*,
+                    ROW_NUMBER() OVER (PARTITION BY folder_id ORDER BY {}, path) AS n
+                FROM ({}) AS page_totals
+            ) AS numbered
+            WHERE n > $1 AND n <= $1 + $2
+            ORDER BY folder_id, n
+            ",
+                    query.order.order_by(query.sort.column()),
+                    Self::PAGE_TOTALS
+                )
+                .as_str(),
+                &[
+                    &(query.offset as i64),
+                    &(query.limit.min(IndexQuery::MAX_LIMIT) as i64),
+                ],
+            )
+            .await?;
+
+        TreeFolder::tree(folders, Self::index_pages(pages), &query)
+    }
+
+    async fn search(&self, query: SearchQuery) -> Result<SearchResults, Error> {
+        let conn = self.db_pool.get().await?;
+        let pattern = query.pattern();
+
+        let total: i64 = conn
+            .query_one(
+                "
+            SELECT COUNT(*)
+            FROM paths
+            INNER JOIN pages
+            ON pages.path_id = paths.path_id
+            WHERE LOWER(paths.path) LIKE $1
+            ",
+                &[&pattern],
+            )
+            .await?
+            .get(0);
+
+        let pages = conn
+            .query(
+                format!(
+                    "
+            SELECT folder_id, path, page_name, views, hits, first_visited
+            FROM ({}) AS page_totals
+            WHERE LOWER(path) LIKE $1
+            ORDER BY {}, path
+            LIMIT $2 OFFSET $3
+            ",
+                    Self::PAGE_TOTALS,
+                    query.order.order_by(query.sort.column())
+                )
+                .as_str(),
+                &[
+                    &pattern,
+                    &(query.limit.min(SearchQuery::MAX_LIMIT) as i64),
+                    &(query.offset as i64),
+                ],
+            )
+            .await?;
+
+        Ok(SearchResults {
+            total,
+            pages: Self::index_pages(pages),
+        })
+    }
+
     // delete_folder
     //
     // Performs a cascading delete on a folder.
@@ -219,8 +766,8 @@ impl DatabaseTool for PostgresDatabaseTools {
     async fn delete_page(&self, folder_id: i32, page_name: String) -> Result<(), Error> {
         let conn = self.db_pool.get().await?;
 
-        let path_id: i32 = conn
-            .query_one(
+        let path_id: i32 = match conn
+            .query_opt(
                 "
             SELECT path_id
             FROM pages
@@ -229,7 +776,10 @@ impl DatabaseTool for PostgresDatabaseTools {
                 &[&page_name, &folder_id],
             )
             .await?
-            .get(0);
+        {
+            Some(p) => p.get(0),
+            None => return not_found(format!("page {} does not exist", page_name)),
+        };
 
         conn.execute(format!("DROP VIEW path_{}", path_id).as_str(), &[])
             .await?;
@@ -256,6 +806,281 @@ impl DatabaseTool for PostgresDatabaseTools {
 
         Ok(())
     }
+    async fn move_page(
+        &self,
+        folder_id: i32,
+        page_name: String,
+        to_folder_id: i32,
+        to_name: String,
+    ) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let (page_id, path_id) = match Self::find_page(&transaction, folder_id, &page_name).await? {
+            Some(p) => p,
+            None => return not_found(format!("page {} does not exist", page_name)),
+        };
+
+        if let Some((id, _)) = Self::find_page(&transaction, to_folder_id, &to_name).await? {
+            if id != page_id {
+                return conflict("a page already exists there - merge it instead");
+            }
+        }
+
+        let path = Self::child_path(
+            &Self::folder_path(&transaction, to_folder_id).await?,
+            &to_name,
+        );
+
+        transaction
+            .execute(
+                "UPDATE pages SET folder_id = $1, page_name = $2 WHERE page_id = $3",
+                &[&to_folder_id, &to_name, &page_id],
+            )
+            .await?;
+        transaction
+            .execute(
+                "UPDATE paths SET path = $1 WHERE path_id = $2",
+                &[&path, &path_id],
+            )
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn move_folder(
+        &self,
+        folder_id: i32,
+        to_parent_id: i32,
+        to_name: String,
+    ) -> Result<(), Error> {
+        if folder_id == 0 {
+            return conflict("the root folder can't be moved");
+        }
+
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let folder = match transaction
+            .query_opt(
+                "SELECT parent_id, folder_name FROM folders WHERE folder_id = $1",
+                &[&folder_id],
+            )
+            .await?
+        {
+            Some(f) => f,
+            None => return not_found(format!("folder {} does not exist", folder_id)),
+        };
+        let parent_id: i32 = folder.get(0);
+        let name: String = folder.get(1);
+
+        // every folder inside this one, and this one
+        let subtree: Vec<i32> = transaction
+            .query(
+                "
+                WITH RECURSIVE subtree (folder_id) AS (
+                    SELECT folder_id
+                    FROM folders
+                    WHERE folder_id = $1
+                    UNION ALL
+                    SELECT folders.folder_id
+                    FROM folders
+                    INNER JOIN subtree
+                    ON folders.parent_id = subtree.folder_id
+                )
+                SELECT folder_id FROM subtree
+                ",
+                &[&folder_id],
+            )
+            .await?
+            .iter()
+            .map(|r| r.get(0))
+            .collect();
+
+        if subtree.contains(&to_parent_id) {
+            return conflict("a folder can't be moved inside itself");
+        }
+
+        if transaction
+            .query_opt(
+                "
+                SELECT folder_id
+                FROM folders
+                WHERE parent_id = $1 AND folder_name = $2 AND folder_id <> $3
+                ",
+                &[&to_parent_id, &to_name, &folder_id],
+            )
+            .await?
+            .is_some()
+        {
+            return conflict("a folder already exists there");
+        }
+
+        let self_page = Self::find_page(&transaction, parent_id, &name).await?;
+        if let Some((id, _)) = Self::find_page(&transaction, to_parent_id, &to_name).await? {
+            if self_page.map(|(p, _)| p) != Some(id) {
+                return conflict("a page with the folder's new name already exists there");
+            }
+        }
+
+        let old_path = Self::folder_path(&transaction, folder_id).await?;
+        let new_path = Self::child_path(
+            &Self::folder_path(&transaction, to_parent_id).await?,
+            &to_name,
+        );
+
+        transaction
+            .execute(
+                "UPDATE folders SET parent_id = $1, folder_name = $2 WHERE folder_id = $3",
+                &[&to_parent_id, &to_name, &folder_id],
+            )
+            .await?;
+
+        transaction
+            .execute(
+                "
+                UPDATE paths
+                SET path = $1::TEXT || SUBSTRING(path FROM CHAR_LENGTH($2::TEXT) + 1)
+                WHERE path_id IN (
+                    SELECT path_id
+                    FROM pages
+                    WHERE folder_id = ANY($3)
+                )
+                ",
+                &[&new_path, &old_path, &subtree],
+            )
+            .await?;
+
+        if let Some((page_id, path_id)) = self_page {
+            transaction
+                .execute(
+                    "UPDATE pages SET folder_id = $1, page_name = $2 WHERE page_id = $3",
+                    &[&to_parent_id, &to_name, &page_id],
+                )
+                .await?;
+            transaction
+                .execute(
+                    "UPDATE paths SET path = $1 WHERE path_id = $2",
+                    &[&new_path, &path_id],
+                )
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn merge_page(
+        &self,
+        folder_id: i32,
+        page_name: String,
+        into_folder_id: i32,
+        into_name: String,
+    ) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        let (from_id, from_path_id) =
+            match Self::find_page(&transaction, folder_id, &page_name).await? {
+                Some(p) => p,
+                None => return not_found(format!("page {} does not exist", page_name)),
+            };
+        let (into_id, _) = match Self::find_page(&transaction, into_folder_id, &into_name).await? {
+            Some(p) => p,
+            None => return not_found(format!("page {} does not exist", into_name)),
+        };
+
+        if from_id == into_id {
+            return conflict("a page can't be merged into itself");
+        }
+
+        // unflushed visitors of both pages are counted once, with their
+        // hits added together
+        transaction
+            .execute(
+                "
+                UPDATE page_visitors AS into_page
+                SET visitor_hits = into_page.visitor_hits + from_page.visitor_hits
+                FROM page_visitors AS from_page
+                WHERE from_page.page_id = $1
+                    AND into_page.page_id = $2
+                    AND from_page.visitor_id = into_page.visitor_id
+                ",
+                &[&from_id, &into_id],
+            )
+            .await?;
+        transaction
+            .execute(
+                "
+                DELETE FROM page_visitors AS from_page
+                USING page_visitors AS into_page
+                WHERE from_page.page_id = $1
+                    AND into_page.page_id = $2
+                    AND from_page.visitor_id = into_page.visitor_id
+                ",
+                &[&from_id, &into_id],
+            )
+            .await?;
+        transaction
+            .execute(
+                "UPDATE page_visitors SET page_id = $2 WHERE page_id = $1",
+                &[&from_id, &into_id],
+            )
+            .await?;
+
+        // history is kept, and added together on days (and months) that
+        // both pages have
+        for (table, period) in &[("page_history", "day"), ("page_history_monthly", "month")] {
+            transaction
+                .execute(
+                    format!(
+                        "
+                INSERT INTO {0} (page_id, {1}, views, hits)
+                SELECT $2, {1}, views, hits
+                FROM {0}
+                WHERE page_id = $1
+                ON CONFLICT (page_id, {1}) DO UPDATE
+                SET
+                    views = {0}.views + EXCLUDED.views,
+                    hits = {0}.hits + EXCLUDED.hits
+                ",
+                        table, period
+                    )
+                    .as_str(),
+                    &[&from_id, &into_id],
+                )
+                .await?;
+        }
+
+        transaction
+            .execute(
+                "
+                UPDATE pages AS into_page
+                SET
+                    total_views = into_page.total_views + from_page.total_views,
+                    total_hits = into_page.total_hits + from_page.total_hits,
+                    first_visited = LEAST(into_page.first_visited, from_page.first_visited)
+                FROM pages AS from_page
+                WHERE from_page.page_id = $1 AND into_page.page_id = $2
+                ",
+                &[&from_id, &into_id],
+            )
+            .await?;
+
+        transaction
+            .execute(format!("DROP VIEW path_{}", from_path_id).as_str(), &[])
+            .await?;
+        transaction
+            .execute("DELETE FROM paths WHERE path_id = $1", &[&from_path_id])
+            .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
 
     async fn get_settings(&self) -> Result<DenViewSettings, Error> {
         let conn = self.db_pool.get().await?;
@@ -337,6 +1162,60 @@ impl DatabaseTool for PostgresDatabaseTools {
         })
     }
 
+    async fn check_password(&self, pass: String) -> Result<bool, Error> {
+        let conn = self.db_pool.get().await?;
+
+        let mut hasher = Sha3::sha3_256();
+        hasher.input_str(&pass);
+        let hashed_pass = hasher.result_str();
+
+        let db_pass: String = serde_json::from_value(
+            conn.query_one(
+                "
+                SELECT setting
+                FROM settings
+                WHERE setting_name = 'password'
+                ",
+                &[],
+            )
+            .await?
+            .get(0),
+        )?;
+
+        Ok(hashed_pass == db_pass)
+    }
+
+    async fn set_credentials(&self, user: Option<String>, pass: String) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().await?;
+        let transaction = conn.transaction().await?;
+
+        if let Some(user) = user {
+            transaction
+                .execute(
+                    "UPDATE settings SET setting = $1 WHERE setting_name = 'user'",
+                    &[&serde_json::to_value(&user)?],
+                )
+                .await?;
+        }
+
+        let mut hasher = Sha3::sha3_256();
+        hasher.input_str(&pass);
+        let hashed_pass = hasher.result_str();
+
+        transaction
+            .execute(
+                "UPDATE settings SET setting = $1 WHERE setting_name = 'password'",
+                &[&serde_json::to_value(&hashed_pass)?],
+            )
+            .await?;
+
+        transaction.execute("DELETE FROM sessions", &[]).await?;
+        transaction.execute("DELETE FROM api_tokens", &[]).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
     async fn init(&self, init: DenViewInit) -> Result<(), Error> {
         log::info!("!!! CREATING DATABASE NOW !!!");
         let mut conn = self.db_pool.get().await?;
@@ -510,10 +1389,224 @@ impl DatabaseTool for PostgresDatabaseTools {
             )
             .await?;
 
+        Self::migrate(&transaction, 1).await?;
+
         log::info!("committing to database");
         transaction.commit().await?;
 
         log::info!("!!! DATABASE CREATION COMPLETE !!!");
         Ok(())
     }
+
+    async fn get_tokens(&self) -> Result<Vec<ApiTokenRecord>, Error> {
+        let conn = self.db_pool.get().await?;
+
+        conn.query(
+            "
+            SELECT token_id, token_name, scopes, created, last_used
+            FROM api_tokens
+            ORDER BY token_id
+            ",
+            &[],
+        )
+        .await?
+        .iter()
+        .map(Self::row_to_token)
+        .collect()
+    }
+
+    async fn create_token(
+        &self,
+        token: ApiTokenRequest,
+        token_hash: String,
+    ) -> Result<ApiTokenRecord, Error> {
+        let conn = self.db_pool.get().await?;
+
+        let row = conn
+            .query_one(
+                "
+            INSERT INTO
+                api_tokens (token_name, token_hash, scopes, created)
+            VALUES
+                ($1, $2, $3, $4)
+            RETURNING
+                token_id, token_name, scopes, created, last_used
+            ",
+                &[
+                    &token.name,
+                    &token_hash,
+                    &serde_json::to_value(&token.scopes)?,
+                    &SystemTime::now(),
+                ],
+            )
+            .await?;
+
+        Self::row_to_token(&row)
+    }
+
+    async fn delete_token(&self, token_id: i32) -> Result<(), Error> {
+        let conn = self.db_pool.get().await?;
+
+        conn.execute("DELETE FROM api_tokens WHERE token_id = $1", &[&token_id])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn check_token(&self, token_hash: String) -> Result<Option<ApiTokenRecord>, Error> {
+        let conn = self.db_pool.get().await?;
+
+        conn.query_opt(
+            "
+            UPDATE api_tokens
+            SET last_used = $1
+            WHERE token_hash = $2
+            RETURNING token_id, token_name, scopes, created, last_used
+            ",
+            &[&SystemTime::now(), &token_hash],
+        )
+        .await?
+        .map(|r| Self::row_to_token(&r))
+        .transpose()
+    }
+
+    async fn create_session(
+        &self,
+        session_hash: String,
+        user: String,
+        csrf_token: String,
+    ) -> Result<(), Error> {
+        let conn = self.db_pool.get().await?;
+        let now = SystemTime::now();
+
+        conn.execute(
+            "
+            INSERT INTO
+                sessions (session_hash, user_name, created, last_seen, csrf_token)
+            VALUES
+                ($1, $2, $3, $3, $4)
+            ",
+            &[&session_hash, &user, &now, &csrf_token],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn check_session(
+        &self,
+        session_hash: String,
+        idle_timeout: u64,
+        max_age: u64,
+    ) -> Result<Option<SessionRecord>, Error> {
+        let conn = self.db_pool.get().await?;
+
+        conn.execute(
+            "DELETE FROM sessions WHERE last_seen < $1 OR created < $2",
+            &[
+                &util::time_before(idle_timeout),
+                &util::time_before(max_age),
+            ],
+        )
+        .await?;
+
+        Ok(conn
+            .query_opt(
+                "
+            UPDATE sessions
+            SET last_seen = $1
+            WHERE session_hash = $2
+            RETURNING user_name, created, last_seen, csrf_token
+            ",
+                &[&SystemTime::now(), &session_hash],
+            )
+            .await?
+            .map(|r| SessionRecord {
+                user: r.get(0),
+                created: util::unix_time(r.get(1)),
+                last_seen: util::unix_time(r.get(2)),
+                csrf_token: r.get(3),
+            }))
+    }
+
+    async fn delete_session(&self, session_hash: String) -> Result<(), Error> {
+        let conn = self.db_pool.get().await?;
+
+        conn.execute(
+            "DELETE FROM sessions WHERE session_hash = $1",
+            &[&session_hash],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), Error> {
+        let conn = self.db_pool.get().await?;
+
+        conn.execute(
+            "
+            INSERT INTO
+                audit_log (logged_at, actor, action, target, before_value, after_value, source_ip)
+            VALUES
+                ($1, $2, $3, $4, $5, $6, $7)
+            ",
+            &[
+                &SystemTime::now(),
+                &entry.actor,
+                &entry.action.as_str(),
+                &entry.target,
+                &entry.before,
+                &entry.after,
+                &entry.source_ip,
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_audit(&self, query: AuditQuery) -> Result<Vec<AuditRecord>, Error> {
+        let conn = self.db_pool.get().await?;
+
+        let rows = conn
+            .query(
+                "
+            SELECT
+                audit_id, logged_at, actor, action, target, before_value, after_value, source_ip
+            FROM audit_log
+            WHERE ($1::TEXT IS NULL OR actor = $1)
+                AND ($2::TEXT IS NULL OR action = $2)
+                AND ($3::TEXT IS NULL OR target = $3)
+                AND ($4::TIMESTAMP IS NULL OR logged_at >= $4)
+                AND ($5::TIMESTAMP IS NULL OR logged_at <= $5)
+            ORDER BY audit_id DESC
+            LIMIT $6 OFFSET $7
+            ",
+                &[
+                    &query.actor,
+                    &query.action,
+                    &query.target,
+                    &query.since.map(util::from_unix_time),
+                    &query.until.map(util::from_unix_time),
+                    &(query.limit.min(AuditQuery::MAX_LIMIT) as i64),
+                    &(query.offset as i64),
+                ],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| AuditRecord {
+                id: r.get(0),
+                time: util::unix_time(r.get(1)),
+                actor: r.get(2),
+                action: r.get(3),
+                target: r.get(4),
+                before: r.get(5),
+                after: r.get(6),
+                source_ip: r.get(7),
+            })
+            .collect())
+    }
 }
@@ -1,9 +1,61 @@
 use crate::util::base64;
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha3::Sha3;
 use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn create_salt() -> String {
+fn random_base64() -> String {
     let mut rng = StdRng::from_entropy();
-    let mut salt_raw: [u8; 32] = [0; 32];
-    rng.fill(&mut salt_raw[..]);
-    base64::bytes_to_base64(salt_raw.to_vec())
+    let mut raw: [u8; 32] = [0; 32];
+    rng.fill(&mut raw[..]);
+    base64::bytes_to_base64(raw.to_vec())
+}
+
+pub fn create_salt() -> String {
+    random_base64()
+}
+
+pub fn create_token() -> String {
+    random_base64()
+}
+
+pub fn hash_token(token: &str) -> String {
+    let mut hasher = Sha3::sha3_256();
+    hasher.input_str(token);
+    hasher.result_str()
+}
+
+// visitor_hash
+//
+// HMAC-SHA3-256 of a visitor's identity, keyed with the current salt, as
+// hex. Every backend has to store visitors with this, so the same visitor
+// is the same ID no matter where it's recorded.
+pub fn visitor_hash(salt: &str, identity: &str) -> String {
+    let mut mac = Hmac::new(Sha3::sha3_256(), salt.as_bytes());
+    mac.input(identity.as_bytes());
+    mac.result()
+        .code()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// The point in time `secs` seconds ago, for comparing against stored
+// timestamps.
+pub fn time_before(secs: u64) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(Duration::from_secs(secs))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+pub fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn from_unix_time(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
 }
@@ -0,0 +1,202 @@
+// error.rs
+//
+// Errors as a client sees them. Anything can still fail with a plain
+// crate::Error; when one reaches the top of a request, it's sorted into
+// an ApiError, and sent back as JSON with a status and a stable code:
+//
+//     {"code": "not_found", "message": "page has not been tracked"}
+//
+// Code that knows what went wrong should return an ApiError directly
+// (boxed, like any other error) so that it isn't guessed at.
+
+use crate::Error;
+use bb8_postgres::tokio_postgres;
+use hyper::{
+    header::{CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE},
+    Body, Response,
+};
+
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden,
+    NotFound(String),
+    MethodNotAllowed,
+    Conflict(String),
+    Internal(String),
+    NotInitialized,
+    Unavailable(String),
+}
+
+impl std::error::Error for ApiError {}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl ApiError {
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::BadRequest(_) => 400,
+            ApiError::Unauthorized => 401,
+            ApiError::Forbidden => 403,
+            ApiError::NotFound(_) => 404,
+            ApiError::MethodNotAllowed => 405,
+            ApiError::Conflict(_) => 409,
+            ApiError::Internal(_) => 500,
+            ApiError::NotInitialized | ApiError::Unavailable(_) => 503,
+        }
+    }
+
+    // These are part of the API: clients match on them, so they should
+    // never change once they've been released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::NotInitialized => "not_initialized",
+            ApiError::Unavailable(_) => "unavailable",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::Unauthorized => "not authorized".into(),
+            ApiError::Forbidden => "forbidden".into(),
+            ApiError::MethodNotAllowed => "method not allowed".into(),
+            ApiError::NotInitialized => "denViews has not been initialized yet".into(),
+            ApiError::BadRequest(m)
+            | ApiError::NotFound(m)
+            | ApiError::Conflict(m)
+            | ApiError::Internal(m)
+            | ApiError::Unavailable(m) => m.clone(),
+        }
+    }
+
+    pub fn response(&self) -> Response<Body> {
+        let body = serde_json::json!({
+            "code": self.code(),
+            "message": self.message(),
+        });
+
+        let mut res = Response::builder()
+            .status(self.status())
+            .header(CONTENT_TYPE, "application/json");
+
+        res = match self {
+            ApiError::Unauthorized => res.header(WWW_AUTHENTICATE, "Basic"),
+            ApiError::Unavailable(_) => res.header(RETRY_AFTER, "5"),
+            _ => res,
+        };
+
+        // nothing above can make this fail
+        res.body(Body::from(body.to_string())).unwrap()
+    }
+
+    // from_error
+    //
+    // Works out what an error means for the client. Database errors are
+    // sorted by what the database says happened; anything that can't be
+    // placed is an internal error.
+    pub fn from_error(e: &Error) -> Self {
+        if let Some(e) = e.downcast_ref::<ApiError>() {
+            return e.clone();
+        }
+
+        if let Some(e) = e.downcast_ref::<tokio_postgres::Error>() {
+            return from_postgres(e);
+        }
+
+        if let Some(e) = e.downcast_ref::<bb8::RunError<tokio_postgres::Error>>() {
+            return match e {
+                bb8::RunError::User(e) => from_postgres(e),
+                bb8::RunError::TimedOut => {
+                    ApiError::Unavailable("timed out waiting for a database connection".into())
+                }
+            };
+        }
+
+        if let Some(e) = e.downcast_ref::<sqlx::Error>() {
+            return from_sqlx(e);
+        }
+
+        if e.is::<serde_qs::Error>() || e.is::<hyper::Error>() || e.is::<http::uri::InvalidUri>() {
+            return ApiError::BadRequest(e.to_string());
+        }
+
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<&Error> for ApiError {
+    fn from(e: &Error) -> Self {
+        ApiError::from_error(e)
+    }
+}
+
+// SQLSTATE classes: 08 is a connection problem, 53 is the server running
+// out of resources (connections included), and 57P is the server shutting down or restarting.
+fn from_sqlstate(state: &str, message: String) -> ApiError {
+    match state {
+        s if s.starts_with("08") || s.starts_with("53") || s.starts_with("57P") => {
+            ApiError::Unavailable(message)
+        }
+        "23505" | "23000" => ApiError::Conflict(message),
+        _ => ApiError::Internal(message),
+    }
+}
+
+fn from_postgres(e: &tokio_postgres::Error) -> ApiError {
+    let io = matches!(std::error::Error::source(e), Some(s) if s.is::<std::io::Error>());
+
+    match e.code() {
+        Some(state) => from_sqlstate(state.code(), e.to_string()),
+        None if e.is_closed() || io => ApiError::Unavailable(e.to_string()),
+        None => ApiError::Internal(e.to_string()),
+    }
+}
+
+fn from_sqlx(e: &sqlx::Error) -> ApiError {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed => ApiError::Unavailable(e.to_string()),
+        sqlx::Error::Database(db) => match db.code() {
+            Some(state) => from_sqlstate(&state, e.to_string()),
+            None => ApiError::Internal(e.to_string()),
+        },
+        _ => ApiError::Internal(e.to_string()),
+    }
+}
+
+// response
+//
+// The response for an error, logged by how bad it is.
+pub fn response(e: Error) -> Response<Body> {
+    let err = ApiError::from_error(&e);
+
+    match err.status() {
+        500..=599 => log::error!("{}", e),
+        _ => log::info!("{}", e),
+    }
+
+    err.response()
+}
+
+// Shorthands for the errors that the database layer knows about.
+pub fn conflict<T>(reason: &str) -> Result<T, Error> {
+    Err(Box::new(ApiError::Conflict(reason.into())))
+}
+
+pub fn not_found<T>(reason: String) -> Result<T, Error> {
+    Err(Box::new(ApiError::NotFound(reason)))
+}
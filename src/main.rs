@@ -1,6 +1,9 @@
 mod dashboard;
 mod database;
+mod error;
+mod recovery;
 mod servers;
+mod tracker;
 mod util;
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -9,5 +12,14 @@ type Error = Box<dyn std::error::Error + Send + Sync>;
 async fn main() {
     tracing_subscriber::fmt().init();
 
-    servers::run().await;
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("reset-password") => {
+            if let Err(e) = recovery::reset_password(args.next()).await {
+                eprintln!("could not reset password: {}", e);
+                std::process::exit(1);
+            }
+        }
+        _ => servers::run().await,
+    }
 }
@@ -0,0 +1,37 @@
+// recovery.rs
+//
+// Offline recovery for a lost dashboard password. This goes straight to
+// the database with the usual DENVIEWS_* connection variables, so it works
+// without the server running, or being reachable at all:
+//
+//     denviews reset-password [new user] < new_password
+//
+// The new password is read as a single line from stdin. As with changing
+// credentials from the dashboard, every session and API token is revoked.
+
+use crate::database::{start_db, DatabaseTool};
+use crate::Error;
+use std::io::BufRead;
+
+pub async fn reset_password(user: Option<String>) -> Result<(), Error> {
+    let (_, tools) = start_db().await?;
+
+    if !tools.check().await? {
+        return Err("denViews has not been initialized yet - there is no password to reset".into());
+    }
+    tools.upgrade().await?;
+
+    eprintln!("enter the new dashboard password:");
+    let mut pass = String::new();
+    std::io::stdin().lock().read_line(&mut pass)?;
+    let pass = pass.trim_end_matches(&['\r', '\n'][..]).to_string();
+
+    if pass.is_empty() {
+        return Err("the new password cannot be empty".into());
+    }
+
+    tools.set_credentials(user, pass).await?;
+    println!("password reset - all sessions and API tokens have been revoked");
+
+    Ok(())
+}
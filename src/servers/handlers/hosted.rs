@@ -1,12 +1,13 @@
 use crate::database::postgres::{database::Postgres, database_tools::PostgresDatabaseTools};
 use crate::database::start_db;
+use crate::error::{self, ApiError};
 use crate::servers::routing::api::{APIHandler, APIRequest};
-use crate::util::base64::base64_to_bytes;
 use crate::Error;
 use async_stream::stream;
 use futures_core::Stream;
 use futures_util::future::join;
 use hyper::{
+    header::{HOST, LOCATION},
     server::accept::Accept,
     server::conn::AddrStream,
     service::{make_service_fn, service_fn},
@@ -15,16 +16,19 @@ use hyper::{
 use std::{
     convert::Infallible,
     fs::File,
+    future::Future,
     io,
     io::BufReader,
     net::SocketAddr,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::ReadBuf;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
 
 // use tokio_native_tls::{native_tls, native_tls::Identity, TlsAcceptor};
 use tokio_rustls::{
@@ -42,6 +46,39 @@ pub async fn serve() {
     let client = Arc::new(APIHandler::new(Arc::new(db), tools).await.unwrap());
     let settings = &client.clone().settings();
 
+    // the servers each take a handle, and this one outlives them
+    let handle = client.clone();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!("shutting down: no longer accepting connections");
+        let _ = shutdown_tx.send(true);
+    });
+
+    // picks up settings changed by other instances; use_https is the one
+    // setting that still needs a restart, since it decides what's bound
+    let poller = client.clone();
+    tokio::spawn(async move {
+        loop {
+            let interval = match poller.settings().settings_poll_interval {
+                0 => 60,
+                i => i,
+            };
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+            poller.poll().await;
+        }
+    });
+
+    // history past its retention is rolled up or deleted once an hour,
+    // starting now
+    let purger = client.clone();
+    tokio::spawn(async move {
+        loop {
+            purger.purge().await;
+            tokio::time::sleep(std::time::Duration::from_secs(60 * 60)).await;
+        }
+    });
+
     match settings.use_https {
         false => {
             let addr = SocketAddr::from(([127, 0, 0, 1], 80));
@@ -53,16 +90,25 @@ pub async fn serve() {
                     Ok::<_, Error>(service_fn(move |req| {
                         let client = client.clone();
 
-                        // in HTTP mode, only when the server is accessed locally is it considered
-                        // authenticated
-                        let auth = ip.ip() == SocketAddr::from(([127, 0, 0, 1], 0)).ip();
+                        // in HTTP mode, the server is always considered authenticated when it's
+                        // accessed locally; anything else needs a token, session or password
+                        let local = ip.ip() == SocketAddr::from(([127, 0, 0, 1], 0)).ip();
 
-                        async move { client.execute(APIRequest { req, ip, auth }).await }
+                        async move {
+                            Ok::<_, Error>(match client.authenticate(&req, local).await {
+                                Ok(auth) => client.execute(APIRequest { req, ip, auth }).await,
+                                Err(e) => error::response(e),
+                            })
+                        }
                     }))
                 }
             });
 
-            Server::bind(&addr).serve(service_wrapper).await.unwrap();
+            let server = Server::bind(&addr)
+                .serve(service_wrapper)
+                .with_graceful_shutdown(shutting_down(shutdown_rx.clone()));
+
+            drain(server, shutdown_rx, settings.shutdown_timeout).await;
         }
         true => {
             let redirect_addr = SocketAddr::from(([127, 0, 0, 1], 80));
@@ -81,6 +127,7 @@ pub async fn serve() {
 
             let listener = TcpListener::bind(addr).await.unwrap();
             let tls = TlsAcceptor::from(Arc::new(tls_config));
+            let metrics = client.metrics();
 
             // This no longer crashes the server, but honestly, a user-friendly error *should* be
             // returned...
@@ -94,7 +141,11 @@ pub async fn serve() {
 
                     match tls_accept {
                         Ok(s) => { yield Ok::<_, io::Error>(IncomingStream::<TlsStream<TcpStream>>(s, ip)); }
-                        Err(e) => { println!("error occurred on tls connect: {}", e); continue; }
+                        Err(e) => {
+                            println!("error occurred on tls connect: {}", e);
+                            metrics.tls_handshake_failed();
+                            continue;
+                        }
                     }
                 }
             }));
@@ -102,25 +153,7 @@ pub async fn serve() {
             // TODO: Get the internal, current representation of the site's authority
             let redirect_wrapper = make_service_fn(move |_: &AddrStream| async move {
                 Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
-                    Ok::<_, Infallible>(
-                        Response::builder()
-                            .status(301)
-                            .header(
-                                "location",
-                                Uri::builder()
-                                    .scheme("https")
-                                    .authority(req.headers()[hyper::header::HOST].to_str().unwrap())
-                                    .path_and_query(match req.uri().path_and_query() {
-                                        Some(v) => v.as_str(),
-                                        None => "",
-                                    })
-                                    .build()
-                                    .unwrap()
-                                    .to_string(),
-                            )
-                            .body(Body::from(""))
-                            .unwrap(),
-                    )
+                    Ok::<_, Infallible>(redirect_to_https(&req))
                 }))
             });
 
@@ -134,42 +167,127 @@ pub async fn serve() {
                             && client.settings().always_auth_locally;
 
                         async move {
-                            let auth = match req.headers().get(hyper::header::AUTHORIZATION) {
-                                None => false,
-                                Some(v) => {
-                                    let auth_header = v.to_str()?.split(' ').collect::<Vec<&str>>();
-                                    match auth_header[0] == "Basic" {
-                                        false => false,
-                                        true => {
-                                            let userpass = String::from_utf8(base64_to_bytes(
-                                                auth_header[1].into(),
-                                            ))?
-                                            .split(':')
-                                            .map(|s| s.into())
-                                            .collect::<Vec<String>>();
-                                            client
-                                                .auth(userpass[0].clone(), userpass[1].clone())
-                                                .await?
-                                        }
-                                    }
-                                }
-                            } || local_auth;
-
-                            client.execute(APIRequest { req, ip, auth }).await
+                            Ok::<_, Error>(match client.authenticate(&req, local_auth).await {
+                                Ok(auth) => client.execute(APIRequest { req, ip, auth }).await,
+                                Err(e) => error::response(e),
+                            })
                         }
                     }))
                 }
             });
 
-            let (res1, res2) = join(
-                Server::builder(stream).serve(service_wrapper),
-                Server::bind(&redirect_addr).serve(redirect_wrapper),
+            let servers = join(
+                Server::builder(stream)
+                    .serve(service_wrapper)
+                    .with_graceful_shutdown(shutting_down(shutdown_rx.clone())),
+                Server::bind(&redirect_addr)
+                    .serve(redirect_wrapper)
+                    .with_graceful_shutdown(shutting_down(shutdown_rx.clone())),
+            );
+
+            drain(
+                async {
+                    let (res1, res2) = servers.await;
+                    res1.and(res2)
+                },
+                shutdown_rx,
+                settings.shutdown_timeout,
             )
             .await;
-            res1.unwrap();
-            res2.unwrap();
         }
     }
+
+    handle.shutdown().await;
+    log::info!("shut down");
+}
+
+// Resolves on Ctrl-C, or on SIGTERM where there is one.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => (),
+                    _ = term.recv() => (),
+                }
+            }
+            Err(e) => {
+                log::error!("could not listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// Resolves once shutdown has started.
+async fn shutting_down(mut rx: watch::Receiver<bool>) {
+    while !*rx.borrow() {
+        if rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+// drain
+//
+// Runs the servers until they stop. Once shutdown starts, requests that
+// are already being handled get `timeout` seconds to finish, after which
+// they're dropped.
+async fn drain(
+    servers: impl Future<Output = Result<(), hyper::Error>>,
+    rx: watch::Receiver<bool>,
+    timeout: u64,
+) {
+    let deadline = async {
+        shutting_down(rx).await;
+        tokio::time::sleep(Duration::from_secs(timeout)).await;
+    };
+
+    tokio::select! {
+        res = servers => {
+            if let Err(e) = res {
+                log::error!("server error: {}", e);
+            }
+        }
+        _ = deadline => {
+            log::warn!("requests still running after {} seconds, dropping them", timeout);
+        }
+    }
+}
+
+// redirect_to_https
+//
+// Sends a plain HTTP request to the same place over HTTPS. Requests
+// without a usable Host header have nowhere to go.
+fn redirect_to_https(req: &Request<Body>) -> Response<Body> {
+    let host = match req.headers().get(HOST).and_then(|h| h.to_str().ok()) {
+        Some(h) => h,
+        None => return ApiError::BadRequest("missing Host header".into()).response(),
+    };
+
+    let location = Uri::builder()
+        .scheme("https")
+        .authority(host)
+        .path_and_query(match req.uri().path_and_query() {
+            Some(v) => v.as_str(),
+            None => "/",
+        })
+        .build();
+
+    match location {
+        Ok(l) => Response::builder()
+            .status(301)
+            .header(LOCATION, l.to_string())
+            .body(Body::from(""))
+            .unwrap_or_else(|e| error::response(Box::new(e))),
+        Err(_) => ApiError::BadRequest("invalid Host header".into()).response(),
+    }
 }
 
 struct TlsStreamWrap(
@@ -1,18 +1,34 @@
 use crate::database::postgres::{database::Postgres, database_tools::PostgresDatabaseTools};
-use crate::database::start_db;
+use crate::database::{start_db, Database, DatabaseTool};
+use crate::error::{self, ApiError};
 use crate::servers::routing::api::{APIHandler, APIRequest};
-use crate::util::base64::base64_to_bytes;
+use crate::servers::routing::auth::Auth;
+use crate::util::base64::{base64_to_bytes, bytes_to_base64};
 use crate::Error;
 use hyper::header::{HeaderName, HeaderValue};
 use lambda_runtime as lambda;
 use serde_json::value::Value;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 // use lambda_runtime::{handler_fn, run, Context};
 
+// The handler is built once per cold start, and kept for every
+// invocation after that. If the runtime ever stops handing out
+// invocations, it shuts down the same way the hosted server does.
 pub async fn run() {
-    lambda::run(lambda::handler_fn(handle)).await.unwrap();
+    let (db, tools) = start_db().await.unwrap();
+    let client = Arc::new(APIHandler::new(Arc::new(db), tools).await.unwrap());
+
+    let handler = client.clone();
+    let res = lambda::run(lambda::handler_fn(move |req, ctx| {
+        let client = handler.clone();
+        async move { handle(client, req, ctx).await }
+    }))
+    .await;
+
+    client.shutdown().await;
+    res.unwrap();
 }
 
 #[derive(serde::Deserialize)]
@@ -86,7 +102,10 @@ impl LambdaAPIGatewayRequest {
 
         let body = match self.is_base64_encoded {
             false => self.body,
-            true => String::from_utf8(base64_to_bytes(self.body))?,
+            true => match base64_to_bytes(&self.body) {
+                Some(b) => String::from_utf8(b)?,
+                None => return Err("malformed base64 body".into()),
+            },
         };
 
         Ok(req.body(hyper::Body::from(body))?)
@@ -213,11 +232,20 @@ impl LambdaAPIGatewayResponse {
         }
 
         let status_code = res.status().as_u16();
-        let body = String::from_utf8(hyper::body::to_bytes(res.into_body()).await?.to_vec())?;
+        let is_base64_encoded = !is_text(
+            res.headers()
+                .get(hyper::header::CONTENT_TYPE)
+                .and_then(|t| t.to_str().ok()),
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await?.to_vec();
+        let body = match is_base64_encoded {
+            true => bytes_to_base64(body),
+            false => String::from_utf8(body)?,
+        };
 
         Ok(LambdaAPIGatewayResponse {
             cookies,
-            is_base64_encoded: false,
+            is_base64_encoded,
             status_code,
             headers,
             body,
@@ -225,6 +253,31 @@ impl LambdaAPIGatewayResponse {
     }
 }
 
+// is_text
+//
+// Whether a body can go back to API Gateway as it is. Anything else (like
+// the tracking pixel) has to be base64, whatever its bytes happen to be.
+// Responses without a body don't have a type, and are sent as text.
+fn is_text(content_type: Option<&str>) -> bool {
+    let essence = match content_type {
+        Some(t) => t
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase(),
+        None => return true,
+    };
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/javascript" | "application/xml"
+        )
+}
+
 #[derive(serde::Deserialize)]
 struct EventBridgeEvent {
     version: String,
@@ -247,40 +300,50 @@ impl std::fmt::Display for HandlerError {
     }
 }
 
-async fn handle(req: Value, _: lambda::Context) -> Result<LambdaAPIGatewayResponse, Error> {
-    log::info!("acquiring db and db_tools connection now");
-    let (db, tools) = start_db().await?;
-    let db = Arc::new(db);
-    log::info!("{:?}", req);
+async fn handle<D: Database, T: DatabaseTool>(
+    client: Arc<APIHandler<D, T>>,
+    req: Value,
+    _: lambda::Context,
+) -> Result<LambdaAPIGatewayResponse, Error> {
+    // nothing runs between invocations, so this is where settings
+    // changed elsewhere get picked up
+    client.refresh().await;
+
     match serde_json::from_value::<LambdaAPIGatewayRequest>(req.clone()) {
         Ok(req) => {
-            let client = APIHandler::new(db, tools).await?;
-            let ip: SocketAddr = format!("{}:0", req.request_context.http.source_ip).parse()?;
+            // only what's needed to find the request again: the event
+            // itself has credentials in its headers, cookies and body
+            log::info!(
+                "{} {} (request {})",
+                req.request_context.http.method,
+                req.raw_path,
+                req.request_context.request_id
+            );
+            // a bare address, IPv4 or IPv6, with no port
+            let ip = match req.request_context.http.source_ip.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, 0),
+                Err(_) => {
+                    return LambdaAPIGatewayResponse::from_response(
+                        ApiError::BadRequest(format!(
+                            "{} is not a valid source IP",
+                            req.request_context.http.source_ip
+                        ))
+                        .response(),
+                    )
+                    .await
+                }
+            };
             let always_auth = match req.stage_variables.get("always_auth") {
                 None => false,
                 Some(v) => v == "true",
             };
-            let req = req.into_request()?;
-            let auth = match req.headers().get(hyper::header::AUTHORIZATION) {
-                None => false,
-                Some(v) => {
-                    let auth_header = v.to_str()?.split(' ').collect::<Vec<&str>>();
-                    match auth_header[0] == "Basic" {
-                        false => false,
-                        true => {
-                            let userpass =
-                                String::from_utf8(base64_to_bytes(auth_header[1].into()))?
-                                    .split(':')
-                                    .map(|s| s.into())
-                                    .collect::<Vec<String>>();
-                            client
-                                .auth(userpass[0].clone(), userpass[1].clone())
-                                .await?
-                        }
-                    }
-                }
-            } || always_auth;
-            let resp = client.execute(APIRequest { req, ip, auth }).await?;
+            let resp = match req.into_request() {
+                Err(e) => ApiError::BadRequest(e.to_string()).response(),
+                Ok(req) => match client.authenticate(&req, always_auth).await {
+                    Ok(auth) => client.execute(APIRequest { req, ip, auth }).await,
+                    Err(e) => error::response(e),
+                },
+            };
 
             return LambdaAPIGatewayResponse::from_response(resp).await;
         }
@@ -291,20 +354,23 @@ async fn handle(req: Value, _: lambda::Context) -> Result<LambdaAPIGatewayRespon
 
     match serde_json::from_value::<EventBridgeEvent>(req) {
         Ok(event) => {
-            if event.resources[0].as_str().contains("denViews_flush") {
-                let client = APIHandler::new(db, tools).await?;
+            if event
+                .resources
+                .iter()
+                .any(|r| r.as_str().contains("denViews_flush"))
+            {
                 let req = hyper::Request::builder()
                     .method("POST")
                     .uri("/_denViews_flush")
-                    .body(hyper::Body::from(""))
-                    .unwrap();
+                    .body(hyper::Body::from(""))?;
                 let resp = client
                     .execute(APIRequest {
                         req,
                         ip: "127.0.0.1:3306".parse()?,
-                        auth: true,
+                        auth: Auth::Local,
                     })
-                    .await?;
+                    .await;
+                client.purge().await;
 
                 return LambdaAPIGatewayResponse::from_response(resp).await;
             }
@@ -1,39 +1,44 @@
+use super::auth::{self, Auth, Credentials};
+use super::badge::{self, BadgeQuery, Metric};
+use super::batch;
+use super::cors;
+use super::health::{self, Readiness};
+use super::identity;
+use super::live::{Live, LiveHandle};
+use super::metrics::{self, Metrics};
+use super::pixel;
 use super::response_utils;
+use super::script;
 use super::tools::ToolsHandler;
 use crate::database::{
-    postgres::database::Postgres, Database, DatabaseOperation, DatabaseTool, DenViewSettings,
+    postgres::database::Postgres, util, AuditAction, AuditEntry, Database, DatabaseOperation,
+    DatabaseTool, DenViewSettings, PrivacySignals, SiteCheck, TokenScope, ViewRecord,
 };
+use crate::error::{self, ApiError};
 use crate::Error;
 use hyper::{
-    header::{LOCATION, USER_AGENT},
-    Body, Client, Method, Request, Response, Uri,
+    header::{CONTENT_TYPE, USER_AGENT},
+    Body, Method, Request, Response, Uri,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use std::{net::SocketAddr, sync::Arc};
 
 pub struct APIHandler<D, T> {
     db: Arc<D>,
     tools: ToolsHandler<T>,
-    settings: Arc<DenViewSettings>,
-    init_check: bool, // lazy, find a better way to do this
+    live: LiveHandle,
+    polled: Mutex<Instant>,
+    metrics: Arc<Metrics>,
 }
 
 pub struct APIRequest {
     pub req: Request<Body>,
     pub ip: SocketAddr,
-    pub auth: bool, // maybe make a struct for this?
-}
-
-#[derive(Debug)]
-pub struct APIError {
-    reason: String,
-}
-
-impl std::error::Error for APIError {}
-
-impl std::fmt::Display for APIError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "api error: {}", self.reason)
-    }
+    pub auth: Auth,
 }
 
 impl<D: Database, T: DatabaseTool> APIHandler<D, T> {
@@ -49,191 +54,700 @@ impl<D: Database, T: DatabaseTool> APIHandler<D, T> {
         let tools = ToolsHandler::new(tools);
         let init_check = tools.check().await?;
 
-        if !init_check {
+        if init_check {
+            tools.upgrade().await?;
+        } else {
             println!("!!!-- denViews MUST be set up before it is ready! Visit https://[host]/_denViews_dash/init and fill out the form! --!!!");
         }
 
         let settings = match init_check {
-            true => Arc::new(db.get_settings().await?),
-            false => Arc::new(DenViewSettings::default()),
+            true => db.get_settings().await?,
+            false => DenViewSettings::default(),
         };
 
         Ok(APIHandler {
             db,
             tools,
-            settings,
-            init_check,
+            live: LiveHandle::new(Live::new(settings, init_check, None)),
+            polled: Mutex::new(Instant::now()),
+            metrics: Arc::new(Metrics::new()?),
         })
     }
 
     pub fn settings(&self) -> Arc<DenViewSettings> {
-        self.settings.clone()
+        self.live.get().settings.clone()
+    }
+
+    #[cfg(feature = "hosted")]
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    // reload
+    //
+    // Reads the settings back out of the database, and swaps them in if
+    // they've changed. This is also how a running denViews goes from
+    // waiting for init to tracking.
+    pub async fn reload(&self) -> Result<(), Error> {
+        let current = self.live.get();
+        let initialized = self.tools.check().await?;
+
+        if initialized && !current.initialized {
+            self.tools.upgrade().await?;
+            log::info!("denViews has been initialized, now tracking");
+        }
+
+        let settings = match initialized {
+            true => self.db.get_settings().await?,
+            false => DenViewSettings::default(),
+        };
+
+        *self.polled.lock().unwrap() = Instant::now();
+
+        if initialized == current.initialized && settings == *current.settings {
+            return Ok(());
+        }
+
+        log::info!("settings changed, applying them now");
+        self.live
+            .swap(Live::new(settings, initialized, Some(&current)));
+
+        Ok(())
+    }
+
+    // poll
+    //
+    // Reloads the settings if `settings_poll_interval` has passed since
+    // they were last loaded, so that changes made through another
+    // instance are picked up. Zero turns this off.
+    pub async fn poll(&self) {
+        let interval = self.settings().settings_poll_interval;
+        if interval == 0 || self.polled.lock().unwrap().elapsed() < Duration::from_secs(interval) {
+            return;
+        }
+
+        if let Err(e) = self.reload().await {
+            log::error!("could not reload settings: {}", e);
+        }
+    }
+
+    // refresh
+    //
+    // What Lambda runs before every invocation, since a warm instance has
+    // no background task to poll with. While denViews is waiting for init,
+    // or when `settings_poll_interval` is zero, the settings are reloaded
+    // every time; otherwise this is the same as poll.
+    #[cfg(feature = "aws-lambda")]
+    pub async fn refresh(&self) {
+        let live = self.live.get();
+        if live.initialized && live.settings.settings_poll_interval != 0 {
+            return self.poll().await;
+        }
+
+        if let Err(e) = self.reload().await {
+            log::error!("could not reload settings: {}", e);
+        }
+    }
+
+    // shutdown
+    //
+    // Runs once nothing else is being handled: flushes visitors if
+    // `flush_on_shutdown` is set, then closes both database pools.
+    pub async fn shutdown(&self) {
+        let live = self.live.get();
+
+        if live.initialized && live.settings.flush_on_shutdown {
+            log::info!("flushing visitors before shutting down");
+            match self.db_op(&live, DatabaseOperation::Flush, None).await {
+                Ok(_) => {
+                    self.tools
+                        .audit(AuditEntry::new(
+                            Auth::Local.actor(),
+                            AuditAction::Flush,
+                            IpAddr::from([127, 0, 0, 1]),
+                        ))
+                        .await
+                }
+                Err(e) => log::error!("could not flush before shutting down: {}", e),
+            }
+        }
+
+        self.db.close().await;
+        self.tools.close().await;
+    }
+
+    // purge
+    //
+    // Rolls up and deletes history that's past the retention settings.
+    // The hosted server runs this on its own; on Lambda, it runs after
+    // every scheduled flush.
+    pub async fn purge(&self) {
+        let live = self.live.get();
+        if !live.initialized {
+            return;
+        }
+
+        if let Err(e) = self
+            .db
+            .purge(
+                live.settings.daily_history_retention,
+                live.settings.monthly_history_retention,
+            )
+            .await
+        {
+            log::error!("could not purge expired history: {}", e);
+        }
     }
 
     pub async fn auth(&self, user: String, pass: String) -> Result<bool, Error> {
         self.tools.auth(user, pass).await
     }
 
-    pub async fn execute(&self, req: APIRequest) -> Result<Response<Body>, Error> {
+    // authenticate
+    //
+    // Resolves the credentials in a request's Authorization header, or its
+    // session cookie if there is no header. `local` is for callers that
+    // trust the connection itself, and wins over anything else.
+    pub async fn authenticate(&self, req: &Request<Body>, local: bool) -> Result<Auth, Error> {
+        if local {
+            return Ok(Auth::Local);
+        }
+
+        let live = self.live.get();
+
+        Ok(match auth::credentials(req) {
+            None => match auth::session_cookie(req) {
+                // sessions only exist once denViews is initialized
+                Some(session) if live.initialized => {
+                    match self
+                        .tools
+                        .check_session(
+                            util::hash_token(&session),
+                            live.settings.session_idle_timeout,
+                            live.settings.session_max_age,
+                        )
+                        .await?
+                    {
+                        Some(s) => Auth::Session(s),
+                        None => Auth::None,
+                    }
+                }
+                _ => Auth::None,
+            },
+            Some(Credentials::Basic(user, pass)) => match self.auth(user.clone(), pass).await? {
+                true => Auth::Basic(user),
+                false => Auth::None,
+            },
+            // tokens only exist once denViews is initialized
+            Some(Credentials::Bearer(_)) if !live.initialized => Auth::None,
+            Some(Credentials::Bearer(token)) => {
+                match self.tools.check_token(util::hash_token(&token)).await? {
+                    Some(t) => Auth::Token(t),
+                    None => Auth::None,
+                }
+            }
+        })
+    }
+
+    // execute
+    //
+    // Always has a response: anything that fails along the way is sent
+    // back as an error (see crate::error) instead of dropping the
+    // connection.
+    pub async fn execute(&self, req: APIRequest) -> Response<Body> {
         log::info!("{:?} {:?}", req.req.method(), req.req.uri());
+        let start = Instant::now();
+        let route = metrics::route(&self.path_as_vec(&req.req));
+        let method = req.req.method().clone();
+
+        let res = match self.route(req).await {
+            Ok(res) => res,
+            Err(e) => error::response(e),
+        };
+
+        self.metrics.request(
+            route,
+            method.as_str(),
+            res.status().as_u16(),
+            start.elapsed(),
+        );
+
+        res
+    }
+
+    async fn route(&self, req: APIRequest) -> Result<Response<Body>, Error> {
         let path = self.path_as_vec(&req.req);
+        let live = self.live.get();
+
+        // health checks and metrics are there whether or not denViews is
+        // initialized. Every path that isn't one of ours is a page to track,
+        // so ours are prefixed; the health checks also answer on the bare
+        // paths orchestrators probe by default.
+        match (req.req.method(), path[0].as_str()) {
+            (&Method::GET, "_denViews_healthz") | (&Method::GET, "healthz") => {
+                return health::liveness()
+            }
+            (&Method::GET, "_denViews_readyz") | (&Method::GET, "readyz") => {
+                return Readiness::check(&*self.db, &self.tools).await.response()
+            }
+            (_, "_denViews_healthz") | (_, "_denViews_readyz") | (_, "healthz") | (_, "readyz") => {
+                return Err(Box::new(ApiError::MethodNotAllowed))
+            }
+            _ => (),
+        }
 
-        if !self.init_check {
+        if path[0] == "_denViews_metrics" {
+            return match (
+                req.req.method(),
+                req.auth.is_authenticated(),
+                req.auth.allows(TokenScope::ReadMetrics),
+            ) {
+                (&Method::GET, _, true) => self.gather_metrics(),
+                (&Method::GET, true, false) => Ok(response_utils::forbidden!()),
+                (&Method::GET, false, _) => Ok(response_utils::request_auth!()),
+                _ => Err(Box::new(ApiError::MethodNotAllowed)),
+            };
+        }
+
+        if !live.initialized {
             return match (req.req.method(), path[0].as_str()) {
-                (_, "_denViews_dash") => match req.auth {
-                    true => self.tools.handle(req.req).await,
+                (_, "_denViews_dash") => match req.auth.is_authenticated() {
+                    true => self.dashboard(req, &path).await,
                     false => Ok(response_utils::request_auth!()),
                 },
 
-                _ => Ok(response_utils::internal_error!(
-                    "denViews has not been initialized yet"
-                )),
+                (&Method::GET, "_denViews_pixel.gif") => {
+                    self.metrics.hit(ApiError::NotInitialized.code());
+                    pixel::response()
+                }
+
+                _ => Err(Box::new(ApiError::NotInitialized)),
             };
         }
 
         match (req.req.method(), path[0].as_str()) {
+            (&Method::OPTIONS, p) => Ok(cors::preflight(
+                &live.settings,
+                &req.req,
+                p != "_denViews_dash" && p != "_denViews_flush",
+            )?),
+
             // TODO: Analytical dashboard for the database. (andauthorizatiomethod)
-            (_, "_denViews_dash") => match req.auth {
-                true => self.tools.handle(req.req).await,
-                false => Ok(response_utils::request_auth!()),
+            (_, "_denViews_dash") => match req.auth.is_authenticated() {
+                true => self.dashboard(req, &path).await,
+                false => {
+                    self.tools
+                        .handle_login(req.req, &live.settings, req.ip.ip())
+                        .await
+                }
             },
 
-            (&Method::POST, "_denViews_flush") => match req.auth {
-                true => self.db_op(DatabaseOperation::Flush, false).await,
-                false => Ok(response_utils::request_auth!()),
-            },
+            (&Method::POST, "_denViews_flush") => {
+                match (
+                    req.auth.is_authenticated(),
+                    req.auth.allows(TokenScope::WriteFlush),
+                ) {
+                    (_, true) => {
+                        let res = self.db_op(&live, DatabaseOperation::Flush, None).await?;
+                        if res.status().is_success() {
+                            self.tools
+                                .audit(AuditEntry::new(
+                                    req.auth.actor(),
+                                    AuditAction::Flush,
+                                    req.ip.ip(),
+                                ))
+                                .await;
+                        }
+
+                        Ok(res)
+                    }
+                    (true, false) => Ok(response_utils::forbidden!()),
+                    (false, _) => Ok(response_utils::request_auth!()),
+                }
+            }
+
+            (&Method::GET, "_denViews_badge") => self.badge(&live, &req.req).await,
+            // a broken image helps nobody looking at the page, so the pixel
+            // is always a pixel, and a hit that isn't counted only shows up
+            // in the logs and metrics
+            (&Method::GET, "_denViews_pixel.gif") => {
+                match pixel::page(&req.req) {
+                    Ok((page, query)) => {
+                        if let Err(e) = self.hit(&live, &page, query.as_deref(), &req).await {
+                            log::info!("pixel hit on {} was not counted: {}", page, e);
+                        }
+                    }
+                    Err(e) => {
+                        log::info!("pixel hit was not counted: {}", e);
+                        self.metrics.hit(ApiError::from_error(&e).code());
+                    }
+                }
+
+                pixel::response()
+            }
+            (&Method::GET, p) if script::is_script(p) => script::response(&req.req, p),
+            (&Method::GET, "_denViews_views") | (&Method::POST, "_denViews_views") => {
+                self.batch(&live, req).await
+            }
+            (_, "_denViews_badge") | (_, "_denViews_pixel.gif") | (_, "_denViews_views") => {
+                Err(Box::new(ApiError::MethodNotAllowed))
+            }
+            (_, p) if script::is_script(p) => Err(Box::new(ApiError::MethodNotAllowed)),
 
             (&Method::GET, _) => {
-                self.db_op(
-                    DatabaseOperation::Get(path.join("/").trim_end_matches('/')),
-                    true,
-                )
-                .await
+                let origin = cors::request_origin(&req.req);
+                let res = self
+                    .db_op(
+                        &live,
+                        DatabaseOperation::Get(&self.tracked_path(&live, &req.req)),
+                        Some(req.req.uri().path()),
+                    )
+                    .await?;
+
+                Ok(cors::apply(&live.settings, origin, res))
             }
             (&Method::POST, _) => {
-                self.db_op(
-                    DatabaseOperation::UpdatePage(
-                        path.join("/").trim_end_matches('/'),
-                        // will the EU scream at me for this? :eye:
-                        &(req.ip.ip().to_string()
-                            + req.req.headers()[USER_AGENT].to_str().unwrap_or("")),
-                    ),
-                    true,
-                )
-                .await
+                let origin = cors::request_origin(&req.req);
+                let res = self
+                    .hit(&live, req.req.uri().path(), req.req.uri().query(), &req)
+                    .await?;
+
+                Ok(cors::apply(&live.settings, origin, res))
             }
 
-            _ => Ok(response_utils::response_with_code!(405)),
+            _ => Err(Box::new(ApiError::MethodNotAllowed)),
         }
     }
 
-    fn path_as_vec(&self, req: &Request<Body>) -> Vec<String> {
-        let mut path: Vec<String>;
-        if let Some(p) = req.uri().path_and_query() {
-            path = p.path()[1..]
-                .split('/')
-                .map(|p| p.to_string())
-                .collect::<Vec<String>>();
-            let path_len = path.len();
-            if self.settings.ignore_queries {
-                if let Some(q) = p.query() {
-                    path[path_len - 1] = [&path[path.len() - 1], q].join("?");
-                }
-            }
-        } else {
-            path = vec!["".into()];
+    // Hands a request to the dashboard, and picks up any changes to the
+    // settings that it made.
+    async fn dashboard(&self, req: APIRequest, path: &[String]) -> Result<Response<Body>, Error> {
+        let changes_settings = req.req.method() == Method::POST
+            && path.get(1).map(|p| p.as_str()) == Some("api")
+            && matches!(
+                path.get(2).map(|p| p.as_str()),
+                Some("settings") | Some("init")
+            );
+
+        let res = self.tools.handle(req.req, &req.auth, req.ip.ip()).await?;
+
+        if changes_settings && res.status().is_success() {
+            self.reload().await?;
+        }
+
+        Ok(res)
+    }
+
+    // hit
+    //
+    // Records a view of `page` (a path, with its leading slash), unless
+    // it's excluded, or the visitor has opted out and `privacy_signals`
+    // says to honor that. `query` is the page's own query, before
+    // normalization, for the exclusion rules.
+    async fn hit(
+        &self,
+        live: &Live,
+        page: &str,
+        query: Option<&str>,
+        req: &APIRequest,
+    ) -> Result<Response<Body>, Error> {
+        let path = &live.normalizer.normalize(page, query);
+        if live.exclusions.excludes(path, query, req.ip.ip()) {
+            log::info!("excluded hit on {}", path);
+            self.metrics.hit("excluded");
+            return Ok(response_utils::response_with_code!(204));
         }
 
-        if self.settings.remove_index_pages && path[path.len() - 1] == "index.html" {
-            path.truncate(path.len() - 1);
+        let anonymous = match (live.settings.privacy_signals, opt_out_signal(&req.req)) {
+            (PrivacySignals::Ignore, _) | (_, None) => false,
+            (PrivacySignals::Skip, Some(signal)) => {
+                log::info!("skipped hit on {}: visitor sent {}", path, signal);
+                self.metrics.opt_out(signal, "skipped");
+                self.metrics.hit("opted_out");
+                return Ok(response_utils::response_with_code!(204));
+            }
+            (PrivacySignals::Anonymous, Some(signal)) => {
+                self.metrics.opt_out(signal, "anonymous");
+                true
+            }
+        };
+
+        // will the EU scream at me for this? :eye:
+        let visitor = match anonymous {
+            true => None,
+            false => identity::visitor(
+                live.settings.visitor_identity,
+                req.ip.ip(),
+                req.req
+                    .headers()
+                    .get(USER_AGENT)
+                    .and_then(|ua| ua.to_str().ok())
+                    .unwrap_or(""),
+            ),
+        };
+
+        let res = self
+            .db_op(
+                live,
+                DatabaseOperation::UpdatePage(path, visitor.as_deref()),
+                Some(page),
+            )
+            .await;
+
+        match &res {
+            Ok(_) => self.metrics.hit(""),
+            Err(e) => self.metrics.hit(ApiError::from_error(e).code()),
         }
 
-        path
+        res
+    }
+
+    // batch
+    //
+    // Views for a list of paths (see batch.rs), keyed by each path as it
+    // was asked for. Every path is looked up in a single query.
+    async fn batch(&self, live: &Live, mut req: APIRequest) -> Result<Response<Body>, Error> {
+        let origin = cors::request_origin(&req.req);
+        let paths = match req.req.method() {
+            &Method::GET => batch::from_query(req.req.uri().query()),
+            _ => batch::from_body(&mut req.req).await?,
+        };
+        let paths = batch::normalize(&live.normalizer, paths)?;
+
+        let mut stored = paths.iter().map(|(_, s)| s.clone()).collect::<Vec<_>>();
+        stored.sort();
+        stored.dedup();
+
+        let found = self
+            .db
+            .get_views(&stored)
+            .await?
+            .into_iter()
+            .map(|r| (r.page, (r.views, r.hits)))
+            .collect::<HashMap<_, _>>();
+
+        let views = paths
+            .into_iter()
+            .map(|(asked, stored)| {
+                let (views, hits) = found.get(&stored).copied().unwrap_or((0, 0));
+                let record = ViewRecord {
+                    page: stored,
+                    views,
+                    hits,
+                };
+                (asked, record)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        Ok(cors::apply(
+            &live.settings,
+            origin,
+            response_utils::ok!(serde_json::to_string(&views)?),
+        ))
+    }
+
+    // badge
+    //
+    // Renders /_denViews_badge/<path>.svg. Reading a count doesn't need
+    // the path checked, and a page that was never tracked just has none.
+    async fn badge(&self, live: &Live, req: &Request<Body>) -> Result<Response<Body>, Error> {
+        let path = match req
+            .uri()
+            .path()
+            .strip_prefix("/_denViews_badge")
+            .and_then(|p| p.strip_suffix(".svg"))
+        {
+            Some(p) => live.normalizer.normalize(p, None),
+            None => return Err(Box::new(ApiError::NotFound("badges end in .svg".into()))),
+        };
+
+        let query = serde_qs::from_str::<BadgeQuery>(req.uri().query().unwrap_or(""))?;
+
+        let count = match self.db.execute(&DatabaseOperation::Get(&path)).await {
+            Ok(Some(r)) => match query.metric {
+                Metric::Views => r.views,
+                Metric::Hits => r.hits,
+            },
+            Ok(None) => 0,
+            Err(e) => match ApiError::from_error(&e) {
+                ApiError::NotFound(_) => 0,
+                _ => return Err(e),
+            },
+        };
+
+        badge::response(badge::render(&query, count)?, live.settings.badge_max_age)
     }
 
-    async fn db_op(&self, op: DatabaseOperation<'_>, check: bool) -> Result<Response<Body>, Error> {
+    // Just the segments of the path, for routing.
+    fn path_as_vec(&self, req: &Request<Body>) -> Vec<String> {
+        req.uri()
+            .path()
+            .get(1..)
+            .unwrap_or("")
+            .split('/')
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>()
+    }
+
+    // The normalized path that a tracking request reads or writes.
+    pub fn tracked_path(&self, live: &Live, req: &Request<Body>) -> String {
+        live.normalizer
+            .normalize(req.uri().path(), req.uri().query())
+    }
+
+    // db_op
+    //
+    // `check` is the path as it was requested, with its leading slash,
+    // which is checked against the site before a page is read or written;
+    // the path in `op` is what it's stored as, and what the check is
+    // cached under. None skips the check.
+    async fn db_op(
+        &self,
+        live: &Live,
+        op: DatabaseOperation<'_>,
+        check: Option<&str>,
+    ) -> Result<Response<Body>, Error> {
         log::info!("running operation: {:?}", op);
         match op {
-            DatabaseOperation::Get(p) | DatabaseOperation::UpdatePage(p, _) => {
-                if check {
-                    let check = self.check_site(p).await;
-                    log::info!("check performed: response was {:?}", check);
-                    match check {
-                        Err(e) => return Ok(response_utils::internal_error!(e)),
-                        Ok(v) => {
-                            if !v.0 {
-                                log::error!("error running site check on {}: {}", p, v.1);
-                                return Ok(response_utils::internal_error!(v.1));
-                            }
+            DatabaseOperation::Get(path) | DatabaseOperation::UpdatePage(path, _) => {
+                if let Some(page) = check {
+                    let (exists, reason) = self.check_site(live, page, path).await?;
+                    log::info!("check performed: response was {:?}", (exists, &reason));
+                    match (exists, reason.is_empty()) {
+                        (true, _) => (),
+                        // the site answered, and the page isn't there
+                        (false, true) => {
+                            return Err(Box::new(ApiError::NotFound(format!(
+                                "{} does not exist on the tracked site",
+                                page
+                            ))))
+                        }
+                        // the site couldn't be reached, so nothing can be said
+                        // about the page either way
+                        (false, false) => {
+                            log::error!("error running site check on {}: {}", page, reason);
+                            return Err(Box::new(ApiError::Unavailable(reason)));
                         }
                     };
                 }
 
-                match self.db.execute(&op).await {
-                    Err(e) => Ok(response_utils::internal_error!(format!(
-                        "{:?} error: {}",
-                        op, e
-                    ))),
-                    Ok(r) => match r {
-                        Some(r) => Ok(Response::builder()
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(serde_json::to_string(&r)?))?),
-                        None => Ok(response_utils::ok!()),
-                    },
+                match self.db.execute(&op).await? {
+                    Some(r) => Ok(response_utils::ok!(serde_json::to_string(&r)?)),
+                    None => Ok(response_utils::ok!()),
                 }
             }
 
-            _ => match self.db.execute(&op).await {
-                Ok(_) => Ok(Response::new(Body::from(""))),
-                Err(e) => Ok(response_utils::internal_error!(format!(
-                    "error performing operation {:?}: {}",
-                    &op,
-                    e.to_string()
-                ))),
-            },
+            _ => {
+                let start = Instant::now();
+                let res = self.db.execute(&op).await;
+                if let DatabaseOperation::Flush = op {
+                    self.metrics.flush(res.is_ok(), start.elapsed());
+                }
+
+                res?;
+                Ok(response_utils::ok!())
+            }
         }
     }
 
-    async fn check_site(&self, path: &str) -> Result<(bool, String), Error> {
+    async fn check_site(
+        &self,
+        live: &Live,
+        page: &str,
+        path: &str,
+    ) -> Result<(bool, String), Error> {
+        let start = Instant::now();
+        let rules = match live.settings.site_check {
+            SiteCheck::Probe => true,
+            SiteCheck::Rules | SiteCheck::Both => live.rules.allows(page).await,
+        };
+
+        let (outcome, res) = match (rules, live.settings.site_check) {
+            (false, _) => (
+                "rejected",
+                Err(Box::new(ApiError::NotFound(format!(
+                    "{} is not allowed by the path rules",
+                    page
+                ))) as Error),
+            ),
+            (true, SiteCheck::Rules) => ("allowed", Ok((true, "".into()))),
+            (true, _) => {
+                let res = self.probe_site(live, page, path).await;
+                let outcome = match &res {
+                    Ok((true, _)) => "exists",
+                    Ok((false, reason)) if reason.is_empty() => "missing",
+                    Ok((false, _)) => "unreachable",
+                    Err(_) => "error",
+                };
+
+                (outcome, res)
+            }
+        };
+
+        self.metrics.check(outcome, start.elapsed());
+        res
+    }
+
+    // Database pools are only looked at when the metrics are gathered,
+    // rather than kept up to date as connections come and go.
+    fn gather_metrics(&self) -> Result<Response<Body>, Error> {
+        self.metrics.pool("tracking", self.db.pool_status());
+        self.metrics.pool("tools", self.tools.pool_status());
+
+        let (content_type, body) = self.metrics.gather()?;
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))?)
+    }
+
+    // Probes `page` as it was requested, but caches the answer under the
+    // stored path without its query, so that every way of asking for the
+    // same page shares one probe.
+    async fn probe_site(
+        &self,
+        live: &Live,
+        page: &str,
+        path: &str,
+    ) -> Result<(bool, String), Error> {
         use http::uri::Scheme;
         use std::convert::TryFrom;
 
-        let tracking = self.settings.site.parse::<Uri>()?.into_parts();
+        let tracking = live.settings.site.parse::<Uri>()?.into_parts();
 
-        if tracking.authority.is_none() {
-            return Err(Box::new(APIError {
-                reason: "no authority found in tracking url".into(),
-            }));
-        }
+        let authority = match tracking.authority {
+            Some(a) => a,
+            None => {
+                return Err(Box::new(ApiError::Internal(
+                    "no authority found in tracking url".into(),
+                )))
+            }
+        };
 
         let uri = Uri::builder()
             .scheme(tracking.scheme.unwrap_or(Scheme::try_from("https")?))
-            .authority(tracking.authority.unwrap())
-            .path_and_query(String::from("/") + path)
+            .authority(authority)
+            .path_and_query(page)
             .build()?;
-        log::info!("checking {:?}", uri);
-        let https = hyper_rustls::HttpsConnector::with_webpki_roots();
-        let client = Client::builder().build::<_, Body>(https);
-        let check = client.get(uri).await;
-        log::debug!("{:?}", check);
-
-        match check {
-            Err(e) => Ok((false, e.to_string())),
-            Ok(r) => {
-                // only a single layer down into the redirect,
-                // to avoid any fuckery
-                if r.status().is_redirection() {
-                    let redirect = r.headers()[LOCATION].to_str()?;
-                    log::info!("redirected, checking {}", redirect);
-                    let result = client.get(redirect.parse()?).await;
-                    return match result {
-                        Err(e) => Ok((false, e.to_string())),
-                        Ok(r) => Ok((r.status().is_success(), "".into())),
-                    };
-                }
 
-                Ok((r.status().is_success(), "".into()))
-            }
-        }
+        let key = path.split('?').next().unwrap_or("");
+        Ok(live.probe.check(key, uri).await)
+    }
+}
+
+// Which opt-out signal a request carries, if any. Global Privacy Control
+// wins when both are sent.
+fn opt_out_signal(req: &Request<Body>) -> Option<&'static str> {
+    let set = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim() == "1")
+            .unwrap_or(false)
+    };
+
+    match (set("sec-gpc"), set("dnt")) {
+        (true, _) => Some("gpc"),
+        (false, true) => Some("dnt"),
+        _ => None,
     }
 }
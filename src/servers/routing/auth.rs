@@ -0,0 +1,104 @@
+// auth.rs
+//
+// Who a request is acting as. Anything that goes to the dashboard or the
+// flush route needs to carry one of these, and API tokens are limited to
+// whatever scopes they were created with.
+
+use crate::database::{ApiTokenRecord, SessionRecord, TokenScope};
+use crate::util::base64::base64_to_bytes;
+use hyper::{
+    header::{AUTHORIZATION, COOKIE},
+    Body, Request,
+};
+
+pub const SESSION_COOKIE: &str = "denviews_session";
+
+#[derive(Debug)]
+pub enum Auth {
+    None,
+
+    // Trusted without credentials: local connections, or the Lambda
+    // always_auth stage variable.
+    Local,
+
+    // HTTP Basic auth with the dashboard user.
+    Basic(String),
+
+    // An API token, sent through `Authorization: Bearer`.
+    Token(ApiTokenRecord),
+
+    // A dashboard login session, sent as a cookie.
+    Session(SessionRecord),
+}
+
+impl Auth {
+    pub fn is_authenticated(&self) -> bool {
+        !matches!(self, Auth::None)
+    }
+
+    // Who to record in the audit log as having done something.
+    pub fn actor(&self) -> String {
+        match self {
+            Auth::None => "anonymous".into(),
+            Auth::Local => "local".into(),
+            Auth::Basic(user) => user.clone(),
+            Auth::Token(t) => format!("token:{}", t.name),
+            Auth::Session(s) => s.user.clone(),
+        }
+    }
+
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        match self {
+            Auth::None => false,
+            Auth::Token(t) => t.scopes.contains(&TokenScope::Admin) || t.scopes.contains(&scope),
+            _ => true,
+        }
+    }
+}
+
+pub enum Credentials {
+    Basic(String, String),
+    Bearer(String),
+}
+
+pub fn credentials(req: &Request<Body>) -> Option<Credentials> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (kind, value) = header.split_once(' ')?;
+
+    match kind {
+        "Basic" => {
+            let userpass = String::from_utf8(base64_to_bytes(value.trim())?).ok()?;
+            let (user, pass) = userpass.split_once(':')?;
+            Some(Credentials::Basic(user.into(), pass.into()))
+        }
+        "Bearer" => Some(Credentials::Bearer(value.trim().into())),
+        _ => None,
+    }
+}
+
+// Gets the session token out of the request's cookies. Lambda passes
+// every cookie as its own header, so all of them are checked.
+pub fn session_cookie(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+pub fn set_session_cookie(token: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; Path=/_denViews_dash; Max-Age={}; HttpOnly; Secure; SameSite=Strict",
+        SESSION_COOKIE, token, max_age
+    )
+}
+
+pub fn clear_session_cookie() -> String {
+    format!(
+        "{}=; Path=/_denViews_dash; Max-Age=0; HttpOnly; Secure; SameSite=Strict",
+        SESSION_COOKIE
+    )
+}
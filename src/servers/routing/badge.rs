@@ -0,0 +1,252 @@
+// badge.rs
+//
+// View counter badges, in the same flat style as shields.io, for places
+// that can show an image but can't run a script: READMEs, static blogs,
+// and so on. Everything about a badge comes from its query:
+//
+// - `label`: the text on the left (views)
+// - `metric`: `views` or `hits` (views)
+// - `format`: `short` for 1.2k, or `full` for 1,234 (short)
+// - `color` and `label_color`: a named color, or a hex color without
+//   the # (blue and grey)
+
+use crate::error::ApiError;
+use crate::Error;
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    Body, Response,
+};
+
+const MAX_LABEL: usize = 64;
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Views,
+    Hits,
+}
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Short,
+    Full,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct BadgeQuery {
+    pub label: String,
+    pub metric: Metric,
+    pub format: Format,
+    pub color: String,
+    pub label_color: String,
+}
+
+impl Default for BadgeQuery {
+    fn default() -> Self {
+        BadgeQuery {
+            label: "views".into(),
+            metric: Metric::Views,
+            format: Format::Short,
+            color: "blue".into(),
+            label_color: "grey".into(),
+        }
+    }
+}
+
+// The same names shields.io has.
+fn parse_color(name: &str) -> Result<String, Error> {
+    let named = match name {
+        "brightgreen" => Some("4c1"),
+        "green" => Some("97ca00"),
+        "yellowgreen" => Some("a4a61d"),
+        "yellow" => Some("dfb317"),
+        "orange" => Some("fe7d37"),
+        "red" => Some("e05d44"),
+        "blue" => Some("007ec6"),
+        "lightgrey" | "lightgray" => Some("9f9f9f"),
+        "grey" | "gray" => Some("555"),
+        "blueviolet" => Some("8a2be2"),
+        _ => None,
+    };
+
+    match named {
+        Some(hex) => Ok(format!("#{}", hex)),
+        None if matches!(name.len(), 3 | 6) && name.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(format!("#{}", name))
+        }
+        None => Err(Box::new(ApiError::BadRequest(format!(
+            "{} is not a color",
+            name
+        )))),
+    }
+}
+
+// format_count
+//
+// 999, 1.0k, 1.2k, 12k, 123k, 1.2M... Anything that would round up into
+// the next unit (999.5k) is shown in that unit instead (1.0M).
+pub fn format_count(count: i64, format: Format) -> String {
+    if let Format::Full = format {
+        let digits = count.unsigned_abs().to_string();
+        let grouped = digits
+            .as_bytes()
+            .rchunks(3)
+            .rev()
+            .map(|g| std::str::from_utf8(g).unwrap_or(""))
+            .collect::<Vec<&str>>()
+            .join(",");
+
+        return match count < 0 {
+            true => format!("-{}", grouped),
+            false => grouped,
+        };
+    }
+
+    if count.unsigned_abs() < 1000 {
+        return count.to_string();
+    }
+
+    let units = ["k", "M", "B", "T"];
+    let mut value = count as f64 / 1000.0;
+    let mut unit = 0;
+
+    loop {
+        let rounded = match value.abs() < 10.0 {
+            true => (value * 10.0).round() / 10.0,
+            false => value.round(),
+        };
+
+        if rounded.abs() < 1000.0 || unit == units.len() - 1 {
+            let shown = match rounded.abs() < 10.0 {
+                true => format!("{:.1}", rounded),
+                false => format!("{:.0}", rounded),
+            };
+            return shown + units[unit];
+        }
+
+        value /= 1000.0;
+        unit += 1;
+    }
+}
+
+// Roughly how wide text is in 11px Verdana, which is what shields.io
+// sizes its badges with. Close enough that nothing gets clipped.
+fn text_width(text: &str) -> u32 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' => 3.5,
+            'f' | 'r' | 't' | 'I' | ' ' | '(' | ')' | '[' | ']' | '-' => 4.5,
+            'm' | 'w' | 'M' | 'W' => 10.5,
+            c if c.is_ascii_uppercase() => 7.5,
+            _ => 7.0,
+        })
+        .sum::<f64>()
+        .ceil() as u32
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn render(query: &BadgeQuery, count: i64) -> Result<String, Error> {
+    let label_text = query.label.chars().take(MAX_LABEL).collect::<String>();
+    let label = escape(&label_text);
+    let value = format_count(count, query.format);
+    let color = parse_color(&query.color)?;
+    let label_color = parse_color(&query.label_color)?;
+
+    let label_width = text_width(&label_text) + 10;
+    let value_width = text_width(&value) + 10;
+    let width = label_width + value_width;
+    let label_x = label_width as f64 / 2.0;
+    let value_x = label_width as f64 + value_width as f64 / 2.0;
+
+    Ok(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}"><title>{label}: {value}</title><linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient><clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="{label_color}"/><rect x="{label_width}" width="{value_width}" height="20" fill="{color}"/><rect width="{width}" height="20" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text><text x="{value_x}" y="15" fill="#010101" fill-opacity=".3">{value}</text><text x="{value_x}" y="14">{value}</text></g></svg>"##,
+        width = width,
+        label = label,
+        value = value,
+        label_width = label_width,
+        value_width = value_width,
+        label_color = label_color,
+        color = color,
+        label_x = label_x,
+        value_x = value_x,
+    ))
+}
+
+// Image proxies and CDNs keep badges for `max_age` seconds, and can keep
+// serving a stale one for as long again while they fetch a new one.
+pub fn response(svg: String, max_age: u64) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "image/svg+xml; charset=utf-8")
+        .header(
+            CACHE_CONTROL,
+            format!(
+                "public, max-age={0}, s-maxage={0}, stale-while-revalidate={0}",
+                max_age
+            ),
+        )
+        .body(Body::from(svg))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_count_short() {
+        let short = |c| format_count(c, Format::Short);
+        assert_eq!(short(0), "0");
+        assert_eq!(short(999), "999");
+        assert_eq!(short(-999), "-999");
+        assert_eq!(short(1000), "1.0k");
+        assert_eq!(short(1234), "1.2k");
+        assert_eq!(short(-1500), "-1.5k");
+        assert_eq!(short(9949), "9.9k");
+        assert_eq!(short(9950), "10k");
+        assert_eq!(short(12_345), "12k");
+        assert_eq!(short(123_456), "123k");
+        assert_eq!(short(999_499), "999k");
+        assert_eq!(short(999_500), "1.0M");
+        assert_eq!(short(1_250_000), "1.3M");
+        assert_eq!(short(1_000_000_000), "1.0B");
+        assert_eq!(short(i64::MAX), "9223372T");
+        assert_eq!(short(i64::MIN), "-9223372T");
+    }
+
+    #[test]
+    fn test_format_count_full() {
+        let full = |c| format_count(c, Format::Full);
+        assert_eq!(full(0), "0");
+        assert_eq!(full(999), "999");
+        assert_eq!(full(1000), "1,000");
+        assert_eq!(full(123_456), "123,456");
+        assert_eq!(full(1_234_567), "1,234,567");
+        assert_eq!(full(-1234), "-1,234");
+        assert_eq!(full(i64::MIN), "-9,223,372,036,854,775,808");
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("blue").unwrap(), "#007ec6");
+        assert_eq!(parse_color("grey").unwrap(), "#555");
+        assert_eq!(parse_color("gray").unwrap(), "#555");
+        assert_eq!(parse_color("lightgray").unwrap(), "#9f9f9f");
+        assert_eq!(parse_color("fff").unwrap(), "#fff");
+        assert_eq!(parse_color("00AAff").unwrap(), "#00AAff");
+
+        assert!(parse_color("").is_err());
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("ffff").is_err());
+        assert!(parse_color("ggg").is_err());
+        assert!(parse_color("Blue").is_err());
+        assert!(parse_color("\"><script>").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+// batch.rs
+//
+// Views for many pages in one request, for index and listing pages.
+// Paths come from the query (`?path=/a&path=/b`) of a GET, or from a JSON
+// array in the body of a POST. Counts are only read, so paths aren't
+// checked against the site, and pages that were never tracked have 0.
+
+use super::normalize;
+use crate::error::ApiError;
+use crate::Error;
+use hyper::{body::to_bytes, Body, Request};
+
+pub const MAX_PATHS: usize = 200;
+
+pub fn from_query(query: Option<&str>) -> Vec<String> {
+    query
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|pair| match pair.split_once('=') {
+            Some(("path", value)) => Some(normalize::percent_decode(&value.replace('+', " "))),
+            _ => None,
+        })
+        .collect()
+}
+
+pub async fn from_body(req: &mut Request<Body>) -> Result<Vec<String>, Error> {
+    match serde_json::from_slice::<Vec<String>>(&to_bytes(req.body_mut()).await?) {
+        Ok(paths) => Ok(paths),
+        Err(e) => Err(Box::new(ApiError::BadRequest(format!(
+            "expected a JSON array of paths: {}",
+            e
+        )))),
+    }
+}
+
+// Every path that was asked for, as given, with the path it's stored as.
+pub fn normalize(
+    normalizer: &normalize::Normalizer,
+    paths: Vec<String>,
+) -> Result<Vec<(String, String)>, Error> {
+    match paths.len() {
+        0 => return Err(Box::new(ApiError::BadRequest("no paths were given".into()))),
+        n if n > MAX_PATHS => {
+            return Err(Box::new(ApiError::BadRequest(format!(
+                "at most {} paths can be looked up at once",
+                MAX_PATHS
+            ))))
+        }
+        _ => (),
+    }
+
+    paths
+        .into_iter()
+        .map(|p| {
+            let (path, query) = normalize::split(&p)?;
+            let stored = normalizer.normalize(&path, query.as_deref());
+            Ok((p, stored))
+        })
+        .collect()
+}
@@ -0,0 +1,191 @@
+// cors.rs
+//
+// Cross-origin access to the public tracking routes. Only origins in the
+// `cors_origins` setting get an Access-Control-Allow-Origin back; if that
+// is empty, the origin of the tracked site is the only one allowed.
+//
+// The dashboard and flush routes never answer cross-origin requests, and
+// credentials are never allowed on any route.
+
+use crate::database::DenViewSettings;
+use hyper::{
+    header::{
+        HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+        ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ALLOW,
+        ORIGIN, VARY,
+    },
+    Body, Method, Request, Response, Uri,
+};
+
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+const ALLOWED_HEADERS: &str = "Content-Type";
+const MAX_AGE: &str = "86400";
+
+// Turns anything that looks like an origin into `scheme://authority`,
+// lowercased, so that the setting and the header can be compared.
+// Origins without a scheme are assumed to be HTTPS.
+fn normalize_origin(origin: &str) -> Option<String> {
+    let parts = origin
+        .trim()
+        .trim_end_matches('/')
+        .parse::<Uri>()
+        .ok()?
+        .into_parts();
+    let scheme = parts
+        .scheme
+        .map(|s| s.as_str().to_string())
+        .unwrap_or_else(|| "https".into());
+
+    Some(format!("{}://{}", scheme, parts.authority?).to_ascii_lowercase())
+}
+
+pub fn allowed_origins(settings: &DenViewSettings) -> Vec<String> {
+    match settings.cors_origins.is_empty() {
+        true => normalize_origin(&settings.site).into_iter().collect(),
+        false => settings
+            .cors_origins
+            .iter()
+            .filter_map(|o| match o.trim() {
+                "*" => Some("*".into()),
+                o => normalize_origin(o),
+            })
+            .collect(),
+    }
+}
+
+pub fn origin_allowed(settings: &DenViewSettings, origin: &str) -> bool {
+    let origin = match normalize_origin(origin) {
+        Some(o) => o,
+        None => return false,
+    };
+
+    allowed_origins(settings)
+        .iter()
+        .any(|a| a == "*" || *a == origin)
+}
+
+pub fn request_origin(req: &Request<Body>) -> Option<HeaderValue> {
+    req.headers().get(ORIGIN).cloned()
+}
+
+// apply
+//
+// Adds the CORS headers for a response to a tracking route. The allowed
+// origin is echoed back rather than sent as `*`, so every response varies
+// on Origin.
+pub fn apply(
+    settings: &DenViewSettings,
+    origin: Option<HeaderValue>,
+    mut res: Response<Body>,
+) -> Response<Body> {
+    let headers = res.headers_mut();
+    headers.append(VARY, HeaderValue::from_static("Origin"));
+
+    if let Some(origin) = origin {
+        if origin
+            .to_str()
+            .map(|o| origin_allowed(settings, o))
+            .unwrap_or(false)
+        {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        }
+    }
+
+    res
+}
+
+// preflight
+//
+// Answers an OPTIONS request. `public` is false for the dashboard and
+// flush routes, which get an empty answer that no browser will accept.
+pub fn preflight(
+    settings: &DenViewSettings,
+    req: &Request<Body>,
+    public: bool,
+) -> Result<Response<Body>, hyper::http::Error> {
+    let mut res = Response::builder()
+        .status(204)
+        .header(VARY, "Origin")
+        .header(ALLOW, ALLOWED_METHODS);
+
+    let method = req
+        .headers()
+        .get(ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|m| m.to_str().ok())
+        .and_then(|m| m.parse::<Method>().ok())
+        .map(|m| m == Method::GET || m == Method::POST)
+        .unwrap_or(false);
+
+    if let (true, true, Some(origin)) = (public, method, request_origin(req)) {
+        if origin
+            .to_str()
+            .map(|o| origin_allowed(settings, o))
+            .unwrap_or(false)
+        {
+            res = res
+                .header(ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                .header(ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+                .header(ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS)
+                .header(ACCESS_CONTROL_MAX_AGE, MAX_AGE);
+        }
+    }
+
+    res.body(Body::from(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(site: &str, cors_origins: &[&str]) -> DenViewSettings {
+        DenViewSettings {
+            site: site.into(),
+            cors_origins: cors_origins.iter().map(|o| o.to_string()).collect(),
+            ..DenViewSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_normalize_origin() {
+        assert_eq!(
+            normalize_origin("https://Example.COM"),
+            Some("https://example.com".into())
+        );
+        assert_eq!(
+            normalize_origin(" https://example.com/ "),
+            Some("https://example.com".into())
+        );
+        assert_eq!(
+            normalize_origin("http://example.com:8080"),
+            Some("http://example.com:8080".into())
+        );
+        assert_eq!(
+            normalize_origin("example.com"),
+            Some("https://example.com".into())
+        );
+        assert_eq!(normalize_origin(""), None);
+        assert_eq!(normalize_origin("/just/a/path"), None);
+    }
+
+    #[test]
+    fn test_origin_allowed() {
+        let site_only = settings("example.com", &[]);
+        assert!(origin_allowed(&site_only, "https://example.com"));
+        assert!(!origin_allowed(&site_only, "http://example.com"));
+        assert!(!origin_allowed(&site_only, "https://sub.example.com"));
+
+        let listed = settings(
+            "example.com",
+            &["https://a.example", "http://b.example:81/"],
+        );
+        assert!(origin_allowed(&listed, "https://A.example"));
+        assert!(origin_allowed(&listed, "http://b.example:81"));
+        assert!(!origin_allowed(&listed, "https://example.com"));
+        assert!(!origin_allowed(&listed, "http://b.example"));
+        assert!(!origin_allowed(&listed, "not an origin"));
+
+        let any = settings("example.com", &["*"]);
+        assert!(origin_allowed(&any, "https://anything.example"));
+        assert!(!origin_allowed(&any, ""));
+    }
+}
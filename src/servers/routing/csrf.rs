@@ -0,0 +1,153 @@
+// csrf.rs
+//
+// Cross-site request checks for the dashboard. Browsers will attach both
+// the session cookie and any cached Basic credentials to a request that
+// another site makes, so anything that changes state has to show that it
+// came from the dashboard itself.
+//
+// API tokens are exempt, since a browser never sends them on its own.
+
+use super::auth::Auth;
+use crate::Error;
+use crypto::util::fixed_time_eq;
+use hyper::{
+    body::to_bytes,
+    header::{HOST, ORIGIN},
+    Body, Request, Uri,
+};
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+const SEC_FETCH_SITE: &str = "sec-fetch-site";
+
+#[derive(serde::Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+// same_origin
+//
+// Checks the browser-provided Sec-Fetch-Site and Origin headers against
+// the host the request was sent to. Browsers send at least one of them
+// with anything that isn't a GET, so a request with neither is turned
+// away too; scripts that change things should use an API token.
+pub fn same_origin(req: &Request<Body>) -> bool {
+    let site = req.headers().get(SEC_FETCH_SITE);
+    let origin = req.headers().get(ORIGIN);
+
+    if let Some(site) = site {
+        if !matches!(site.to_str(), Ok("same-origin") | Ok("none")) {
+            return false;
+        }
+    }
+
+    let origin = match origin {
+        None => return site.is_some(),
+        Some(o) => match o.to_str().ok().and_then(|o| o.parse::<Uri>().ok()) {
+            None => return false,
+            Some(o) => o,
+        },
+    };
+
+    let host = match req.uri().authority() {
+        Some(a) => Some(a.as_str()),
+        None => req.headers().get(HOST).and_then(|h| h.to_str().ok()),
+    };
+
+    match (origin.authority(), host) {
+        (Some(o), Some(h)) => o.as_str().eq_ignore_ascii_case(h),
+        _ => false,
+    }
+}
+
+// verify
+//
+// The full check for a state-changing request. Sessions also have to send
+// their CSRF token, either as the X-CSRF-Token header or as a csrf_token
+// form field. The body is read to find the field, and then put back.
+pub async fn verify(req: &mut Request<Body>, auth: &Auth) -> Result<bool, Error> {
+    if let Auth::Token(_) = auth {
+        return Ok(true);
+    }
+
+    if !same_origin(req) {
+        return Ok(false);
+    }
+
+    let session = match auth {
+        Auth::Session(s) => s,
+        _ => return Ok(true),
+    };
+
+    let token = match req.headers().get(CSRF_HEADER) {
+        Some(v) => v.to_str().ok().map(|v| v.to_string()),
+        None => {
+            let body = to_bytes(req.body_mut()).await?;
+            let token = serde_qs::Config::new(5, false)
+                .deserialize_bytes::<CsrfForm>(&body)
+                .ok()
+                .and_then(|f| f.csrf_token);
+            *req.body_mut() = Body::from(body);
+            token
+        }
+    };
+
+    Ok(match token {
+        None => false,
+        Some(t) => fixed_time_eq(t.as_bytes(), session.csrf_token.as_bytes()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request<Body> {
+        let mut req = Request::builder()
+            .method("POST")
+            .uri("/_denViews_dash/api/settings")
+            .header(HOST, "views.example.com");
+        for (k, v) in headers {
+            req = req.header(*k, *v);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_same_origin_headers() {
+        assert!(!same_origin(&request(&[])));
+        assert!(same_origin(&request(&[(
+            "origin",
+            "https://views.example.com"
+        )])));
+        assert!(same_origin(&request(&[(
+            "origin",
+            "https://VIEWS.example.com"
+        )])));
+        assert!(same_origin(&request(&[("sec-fetch-site", "same-origin")])));
+        assert!(same_origin(&request(&[("sec-fetch-site", "none")])));
+        assert!(!same_origin(&request(&[("sec-fetch-site", "cross-site")])));
+        assert!(!same_origin(&request(&[("sec-fetch-site", "same-site")])));
+        assert!(!same_origin(&request(&[("origin", "https://example.com")])));
+        assert!(!same_origin(&request(&[(
+            "origin",
+            "https://views.example.com:8443"
+        )])));
+        assert!(!same_origin(&request(&[("origin", "null")])));
+    }
+
+    #[test]
+    fn test_same_origin_both_headers() {
+        assert!(same_origin(&request(&[
+            ("sec-fetch-site", "same-origin"),
+            ("origin", "https://views.example.com"),
+        ])));
+        assert!(!same_origin(&request(&[
+            ("sec-fetch-site", "same-origin"),
+            ("origin", "https://evil.example"),
+        ])));
+        assert!(!same_origin(&request(&[
+            ("sec-fetch-site", "cross-site"),
+            ("origin", "https://views.example.com"),
+        ])));
+    }
+}
@@ -0,0 +1,196 @@
+// exclude.rs
+//
+// Hits that should be accepted but never counted: preview and draft
+// pages, admin paths, or visits from our own network. Excluded hits get
+// a 204 back, and never reach the database.
+//
+// `exclude_paths` and `exclude_queries` take the same rules as
+// `path_rules` (see rules.rs). Path rules match the normalized path, and
+// query rules match each `name=value` pair of the original query.
+// `exclude_ips` takes addresses, or ranges in CIDR notation.
+
+use super::rules::Rule;
+use crate::database::DenViewSettings;
+use std::net::IpAddr;
+
+struct IpRange {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl IpRange {
+    fn parse(range: &str) -> Option<Self> {
+        let (addr, prefix) = match range.split_once('/') {
+            Some((a, p)) => (a.parse::<IpAddr>().ok()?, Some(p.parse::<u32>().ok()?)),
+            None => (range.parse::<IpAddr>().ok()?, None),
+        };
+
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        match prefix.unwrap_or(bits) {
+            p if p > bits => None,
+            prefix => Some(IpRange { addr, prefix }),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let (range, ip, bits) = match (self.addr, ip) {
+            (IpAddr::V4(r), IpAddr::V4(i)) => (u32::from(r) as u128, u32::from(i) as u128, 32),
+            (IpAddr::V6(r), IpAddr::V6(i)) => (u128::from(r), u128::from(i), 128),
+            // IPv4 visitors that arrive over IPv6, and IPv6 ranges
+            // written for them (::ffff:0:0/96)
+            (IpAddr::V4(_), IpAddr::V6(i)) => {
+                return match i.to_ipv4_mapped() {
+                    Some(i) => self.contains(IpAddr::V4(i)),
+                    None => false,
+                }
+            }
+            (IpAddr::V6(_), IpAddr::V4(i)) => return self.contains(IpAddr::V6(i.to_ipv6_mapped())),
+        };
+
+        match self.prefix {
+            0 => true,
+            p => (range ^ ip) >> (bits - p) == 0,
+        }
+    }
+}
+
+pub struct Exclusions {
+    paths: Vec<Rule>,
+    queries: Vec<Rule>,
+    ips: Vec<IpRange>,
+}
+
+impl Exclusions {
+    pub fn new(settings: &DenViewSettings) -> Self {
+        let ips = settings
+            .exclude_ips
+            .iter()
+            .map(|r| r.trim())
+            .filter(|r| !r.is_empty())
+            .filter_map(|r| match IpRange::parse(r) {
+                Some(r) => Some(r),
+                None => {
+                    log::error!("ignoring invalid IP range {}", r);
+                    None
+                }
+            })
+            .collect();
+
+        Exclusions {
+            paths: Rule::parse_list(&settings.exclude_paths),
+            queries: Rule::parse_list(&settings.exclude_queries),
+            ips,
+        }
+    }
+
+    // excludes
+    //
+    // `path` is the normalized path, as stored; `query` is the request's
+    // own query, before normalization drops any of it.
+    pub fn excludes(&self, path: &str, query: Option<&str>, ip: IpAddr) -> bool {
+        let path = String::from("/") + path.split('?').next().unwrap_or("");
+
+        self.paths.iter().any(|r| r.matches(&path))
+            || query
+                .map(|q| {
+                    q.split('&')
+                        .any(|pair| self.queries.iter().any(|r| r.matches(pair)))
+                })
+                .unwrap_or(false)
+            || self.ips.iter().any(|r| r.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(r: &str) -> IpRange {
+        IpRange::parse(r).unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_range_parse() {
+        assert!(IpRange::parse("10.0.0.1").is_some());
+        assert!(IpRange::parse("10.0.0.0/0").is_some());
+        assert!(IpRange::parse("10.0.0.0/32").is_some());
+        assert!(IpRange::parse("10.0.0.0/33").is_none());
+        assert!(IpRange::parse("10.0.0.0/").is_none());
+        assert!(IpRange::parse("10.0.0.0/-1").is_none());
+        assert!(IpRange::parse("10.0.0/8").is_none());
+        assert!(IpRange::parse("2001:db8::/128").is_some());
+        assert!(IpRange::parse("2001:db8::/129").is_none());
+        assert!(IpRange::parse("example.com").is_none());
+    }
+
+    #[test]
+    fn test_ipv4_ranges() {
+        let r = range("192.168.1.0/24");
+        assert!(r.contains(ip("192.168.1.0")));
+        assert!(r.contains(ip("192.168.1.255")));
+        assert!(!r.contains(ip("192.168.0.255")));
+        assert!(!r.contains(ip("192.168.2.0")));
+
+        // host bits in the range itself don't matter
+        assert!(range("192.168.1.77/24").contains(ip("192.168.1.1")));
+
+        assert!(range("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!range("10.0.0.1").contains(ip("10.0.0.2")));
+        assert!(range("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(!range("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(range("10.0.0.0/7").contains(ip("11.255.255.255")));
+        assert!(!range("10.0.0.0/7").contains(ip("12.0.0.0")));
+    }
+
+    #[test]
+    fn test_ipv6_ranges() {
+        let r = range("2001:db8:1::/48");
+        assert!(r.contains(ip("2001:db8:1::")));
+        assert!(r.contains(ip("2001:db8:1:ffff:ffff:ffff:ffff:ffff")));
+        assert!(!r.contains(ip("2001:db8:2::")));
+        assert!(!r.contains(ip("192.168.1.1")));
+        assert!(range("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn test_ipv4_mapped() {
+        assert!(range("192.168.1.0/24").contains(ip("::ffff:192.168.1.20")));
+        assert!(!range("192.168.1.0/24").contains(ip("::ffff:192.168.2.20")));
+        assert!(range("::ffff:0:0/96").contains(ip("198.51.100.1")));
+        assert!(range("::ffff:192.168.1.0/120").contains(ip("192.168.1.20")));
+        assert!(!range("::ffff:192.168.1.0/120").contains(ip("192.168.2.20")));
+    }
+
+    #[test]
+    fn test_excludes() {
+        let e = Exclusions::new(&DenViewSettings {
+            exclude_paths: vec!["/drafts/".into(), "glob:/**/preview".into()],
+            exclude_queries: vec!["preview=1".into(), "regex:^utm_source=internal$".into()],
+            exclude_ips: vec!["10.0.0.0/8".into(), "not an ip".into()],
+            ..DenViewSettings::default()
+        });
+        let visitor = ip("203.0.113.9");
+
+        assert!(!e.excludes("blog/post", None, visitor));
+        assert!(e.excludes("drafts/post", None, visitor));
+        assert!(!e.excludes("drafts", None, visitor));
+        assert!(e.excludes("blog/post/preview", None, visitor));
+        assert!(e.excludes("blog/post?x=1", Some("a=b&preview=1"), visitor));
+        // a rule without a kind is a prefix of the pair
+        assert!(e.excludes("blog/post", Some("preview=10"), visitor));
+        assert!(!e.excludes("blog/post", Some("preview=0"), visitor));
+        assert!(!e.excludes("blog/post", Some("a=preview=1"), visitor));
+        assert!(e.excludes("blog/post", Some("utm_source=internal"), visitor));
+        assert!(!e.excludes("blog/post", Some("utm_source=internal2"), visitor));
+        assert!(e.excludes("blog/post", None, ip("10.1.2.3")));
+        assert!(e.excludes("blog/post", None, ip("::ffff:10.1.2.3")));
+    }
+}
@@ -0,0 +1,95 @@
+// health.rs
+//
+// Probes for load balancers and orchestrators. Liveness only says that
+// the process is answering requests; readiness says whether it can track
+// anything, and if it can't, why:
+//
+// - `database_down`: a pool couldn't get a working connection
+// - `not_initialized`: the database is up, but init hasn't been run
+// - `schema_mismatch`: the stored schema isn't the one this build expects
+//
+// Neither needs credentials, so database errors are only logged, and
+// never sent back.
+
+use super::probe::with_timeout;
+use super::tools::ToolsHandler;
+use crate::database::{Database, DatabaseTool, PoolStatus, SCHEMA_VERSION};
+use crate::Error;
+use futures_util::future::join;
+use hyper::{header::CONTENT_TYPE, Body, Response};
+use std::time::Duration;
+
+// Well under what most probes wait before giving up, so that a database
+// that's hanging is reported as down rather than the probe timing out.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(serde::Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    database: DatabaseHealth,
+    initialized: Option<bool>,
+    schema_version: Option<i64>,
+    expected_schema_version: i64,
+}
+
+#[derive(serde::Serialize)]
+struct DatabaseHealth {
+    reachable: bool,
+    tracking_pool: PoolStatus,
+    tools_pool: PoolStatus,
+}
+
+impl Readiness {
+    pub async fn check<D: Database, T: DatabaseTool>(db: &D, tools: &ToolsHandler<T>) -> Self {
+        let (ping, version) = join(
+            with_timeout(CHECK_TIMEOUT, db.ping()),
+            with_timeout(CHECK_TIMEOUT, tools.schema_version()),
+        )
+        .await;
+
+        if let Err(e) = &ping {
+            log::error!("readiness: tracking pool is down: {}", e);
+        }
+        if let Err(e) = &version {
+            log::error!("readiness: tools pool is down: {}", e);
+        }
+
+        let reachable = ping.is_ok() && version.is_ok();
+        let version = version.ok().flatten();
+
+        let (status, initialized) = match (reachable, version) {
+            (false, _) => ("database_down", None),
+            (true, None) => ("not_initialized", Some(false)),
+            (true, Some(v)) if v != SCHEMA_VERSION => ("schema_mismatch", Some(true)),
+            (true, Some(_)) => ("ready", Some(true)),
+        };
+
+        Readiness {
+            status,
+            database: DatabaseHealth {
+                reachable,
+                tracking_pool: db.pool_status(),
+                tools_pool: tools.pool_status(),
+            },
+            initialized,
+            schema_version: version,
+            expected_schema_version: SCHEMA_VERSION,
+        }
+    }
+
+    pub fn response(&self) -> Result<Response<Body>, Error> {
+        Ok(Response::builder()
+            .status(match self.status {
+                "ready" => 200,
+                _ => 503,
+            })
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(self)?))?)
+    }
+}
+
+pub fn liveness() -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"status":"ok"}"#))?)
+}
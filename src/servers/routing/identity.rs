@@ -0,0 +1,101 @@
+// identity.rs
+//
+// What a visitor is recorded as, before it's hashed (see
+// database::util::visitor_hash). Which parts of a request go into it is
+// up to the `visitor_identity` setting.
+
+use crate::database::VisitorIdentity;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Drops the host part of an address: everything past the /24 for IPv4,
+// and past the /48 for IPv6. IPv4 addresses mapped into IPv6 are
+// truncated as IPv4.
+pub fn truncate(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => truncate_v4(v4),
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => truncate_v4(Ipv4Addr::new(
+                (hi >> 8) as u8,
+                hi as u8,
+                (lo >> 8) as u8,
+                lo as u8,
+            )),
+            [a, b, c, ..] => IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0)),
+        },
+    }
+}
+
+fn truncate_v4(ip: Ipv4Addr) -> IpAddr {
+    let [a, b, c, _] = ip.octets();
+    IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+}
+
+// visitor
+//
+// The identity of a visitor, or nothing if visitors aren't told apart at
+// all. Fields are separated by a newline, which can't be in either of
+// them.
+pub fn visitor(mode: VisitorIdentity, ip: IpAddr, user_agent: &str) -> Option<String> {
+    match mode {
+        VisitorIdentity::Ip => Some(format!("{}\n{}", ip, user_agent)),
+        VisitorIdentity::TruncatedIp => Some(format!("{}\n{}", truncate(ip), user_agent)),
+        VisitorIdentity::UserAgent => Some(format!("\n{}", user_agent)),
+        VisitorIdentity::None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate(ip("203.0.113.77")), ip("203.0.113.0"));
+        assert_eq!(truncate(ip("203.0.113.0")), ip("203.0.113.0"));
+        assert_eq!(truncate(ip("255.255.255.255")), ip("255.255.255.0"));
+        assert_eq!(
+            truncate(ip("2001:db8:abcd:12:34:56:78:9a")),
+            ip("2001:db8:abcd::")
+        );
+        assert_eq!(truncate(ip("::1")), ip("::"));
+
+        // the same visitor, whether or not it came in over IPv6
+        assert_eq!(truncate(ip("::ffff:203.0.113.77")), ip("203.0.113.0"));
+        // only mapped addresses are treated as IPv4
+        assert_eq!(truncate(ip("::203.0.113.77")), ip("::"));
+    }
+
+    #[test]
+    fn test_visitor() {
+        let addr = ip("203.0.113.77");
+        let ua = "Mozilla/5.0";
+
+        assert_eq!(
+            visitor(VisitorIdentity::Ip, addr, ua).unwrap(),
+            "203.0.113.77\nMozilla/5.0"
+        );
+        assert_eq!(
+            visitor(VisitorIdentity::TruncatedIp, addr, ua).unwrap(),
+            "203.0.113.0\nMozilla/5.0"
+        );
+        assert_eq!(
+            visitor(VisitorIdentity::UserAgent, addr, ua).unwrap(),
+            "\nMozilla/5.0"
+        );
+        assert_eq!(visitor(VisitorIdentity::None, addr, ua), None);
+
+        // neighbours only look the same once the address is truncated
+        assert_ne!(
+            visitor(VisitorIdentity::Ip, ip("203.0.113.78"), ua),
+            visitor(VisitorIdentity::Ip, addr, ua)
+        );
+        assert_eq!(
+            visitor(VisitorIdentity::TruncatedIp, ip("203.0.113.78"), ua),
+            visitor(VisitorIdentity::TruncatedIp, addr, ua)
+        );
+    }
+}
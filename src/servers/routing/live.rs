@@ -0,0 +1,66 @@
+// live.rs
+//
+// The settings that requests are currently being handled with, and
+// everything built from them. A request takes a snapshot when it starts,
+// and keeps it until it's done; when the settings change, a new snapshot
+// is swapped in for the requests after it.
+//
+// The probe cache and the sitemap are carried over into the new snapshot
+// if none of the settings they're built from changed, so that saving
+// unrelated settings doesn't set off a round of requests to the site.
+
+use super::exclude::Exclusions;
+use super::normalize::Normalizer;
+use super::probe::{self, SiteProbe};
+use super::rules::PathRules;
+use crate::database::DenViewSettings;
+use std::sync::{Arc, RwLock};
+
+pub struct Live {
+    pub settings: Arc<DenViewSettings>,
+    pub initialized: bool,
+    pub normalizer: Normalizer,
+    pub exclusions: Exclusions,
+    pub rules: Arc<PathRules>,
+    pub probe: Arc<SiteProbe>,
+}
+
+impl Live {
+    pub fn new(settings: DenViewSettings, initialized: bool, previous: Option<&Live>) -> Self {
+        let client = probe::http_client(&settings);
+
+        let rules = match previous {
+            Some(p) if PathRules::unchanged(&p.settings, &settings) => p.rules.clone(),
+            _ => Arc::new(PathRules::new(&settings, client.clone())),
+        };
+        let probe = match previous {
+            Some(p) if SiteProbe::unchanged(&p.settings, &settings) => p.probe.clone(),
+            _ => Arc::new(SiteProbe::new(&settings, client)),
+        };
+
+        Live {
+            normalizer: Normalizer::new(&settings),
+            exclusions: Exclusions::new(&settings),
+            rules,
+            probe,
+            settings: Arc::new(settings),
+            initialized,
+        }
+    }
+}
+
+pub struct LiveHandle(RwLock<Arc<Live>>);
+
+impl LiveHandle {
+    pub fn new(live: Live) -> Self {
+        LiveHandle(RwLock::new(Arc::new(live)))
+    }
+
+    pub fn get(&self) -> Arc<Live> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn swap(&self, live: Live) {
+        *self.0.write().unwrap() = Arc::new(live);
+    }
+}
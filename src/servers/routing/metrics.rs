@@ -0,0 +1,201 @@
+// metrics.rs
+//
+// Prometheus metrics, served in the text format from /_denViews_metrics.
+// Everything is registered in a registry of its own instead of the
+// global one, so that only what's here ever gets exposed.
+//
+// Label values are all fixed strings (routes are grouped, and reasons
+// are error codes), so none of them can grow with the number of pages.
+
+use crate::database::{util, PoolStatus};
+use crate::Error;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::time::{Duration, SystemTime};
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    hits: IntCounterVec,
+    checks: IntCounterVec,
+    opt_outs: IntCounterVec,
+    check_duration: Histogram,
+    pool_connections: IntGaugeVec,
+    flush_duration: Histogram,
+    flush_last_success: IntGauge,
+    // Lambda never sees a handshake
+    #[cfg(feature = "hosted")]
+    tls_handshake_failures: prometheus::IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, Error> {
+        let registry = Registry::new_custom(Some("denviews".into()), None)?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["route", "method", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["route"],
+        )?;
+        let hits = IntCounterVec::new(
+            Opts::new("hits_total", "Hits received, by whether they were counted"),
+            &["result", "reason"],
+        )?;
+        let checks = IntCounterVec::new(
+            Opts::new("site_checks_total", "Path checks, by outcome"),
+            &["outcome"],
+        )?;
+        let opt_outs = IntCounterVec::new(
+            Opts::new(
+                "privacy_opt_outs_total",
+                "Hits from visitors that asked not to be tracked, by signal and what was done",
+            ),
+            &["signal", "action"],
+        )?;
+        let check_duration = Histogram::with_opts(HistogramOpts::new(
+            "site_check_duration_seconds",
+            "Time taken to check a path",
+        ))?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections"),
+            &["pool", "state"],
+        )?;
+        let flush_duration = Histogram::with_opts(
+            HistogramOpts::new("flush_duration_seconds", "Time taken to flush visitors")
+                .buckets(vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]),
+        )?;
+        let flush_last_success = IntGauge::new(
+            "flush_last_success_timestamp_seconds",
+            "Unix time of the last successful flush",
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(hits.clone()))?;
+        registry.register(Box::new(checks.clone()))?;
+        registry.register(Box::new(opt_outs.clone()))?;
+        registry.register(Box::new(check_duration.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(flush_duration.clone()))?;
+        registry.register(Box::new(flush_last_success.clone()))?;
+
+        #[cfg(feature = "hosted")]
+        let tls_handshake_failures = prometheus::IntCounter::new(
+            "tls_handshake_failures_total",
+            "TLS handshakes that failed before a request was read",
+        )?;
+        #[cfg(feature = "hosted")]
+        registry.register(Box::new(tls_handshake_failures.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            requests,
+            request_duration,
+            hits,
+            checks,
+            opt_outs,
+            check_duration,
+            pool_connections,
+            flush_duration,
+            flush_last_success,
+            #[cfg(feature = "hosted")]
+            tls_handshake_failures,
+        })
+    }
+
+    pub fn request(&self, route: &str, method: &str, status: u16, took: Duration) {
+        self.requests
+            .with_label_values(&[route, method, &status.to_string()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[route])
+            .observe(took.as_secs_f64());
+    }
+
+    // `reason` is empty for hits that were counted.
+    pub fn hit(&self, reason: &str) {
+        match reason {
+            "" => self.hits.with_label_values(&["ingested", ""]).inc(),
+            r => self.hits.with_label_values(&["rejected", r]).inc(),
+        }
+    }
+
+    // `signal` is dnt or gpc, and `action` is skipped or anonymous.
+    pub fn opt_out(&self, signal: &str, action: &str) {
+        self.opt_outs.with_label_values(&[signal, action]).inc();
+    }
+
+    pub fn check(&self, outcome: &str, took: Duration) {
+        self.checks.with_label_values(&[outcome]).inc();
+        self.check_duration.observe(took.as_secs_f64());
+    }
+
+    pub fn flush(&self, success: bool, took: Duration) {
+        self.flush_duration.observe(took.as_secs_f64());
+
+        if success {
+            self.flush_last_success
+                .set(util::unix_time(SystemTime::now()));
+        }
+    }
+
+    #[cfg(feature = "hosted")]
+    pub fn tls_handshake_failed(&self) {
+        self.tls_handshake_failures.inc();
+    }
+
+    // Pools are only looked at when metrics are gathered.
+    pub fn pool(&self, pool: &str, status: PoolStatus) {
+        self.pool_connections
+            .with_label_values(&[pool, "idle"])
+            .set(status.idle as i64);
+        self.pool_connections
+            .with_label_values(&[pool, "in_use"])
+            .set(status.size.saturating_sub(status.idle) as i64);
+        self.pool_connections
+            .with_label_values(&[pool, "max"])
+            .set(status.max as i64);
+    }
+
+    pub fn gather(&self) -> Result<(String, Vec<u8>), Error> {
+        let encoder = TextEncoder::new();
+        let mut buf = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buf)?;
+
+        Ok((encoder.format_type().to_string(), buf))
+    }
+}
+
+// route
+//
+// The route label for a request. Everything that isn't one of denViews'
+// own routes is a tracked page, and is counted as one route.
+pub fn route(path: &[String]) -> &'static str {
+    match (
+        path.first().map(|p| p.as_str()),
+        path.get(1).map(|p| p.as_str()),
+    ) {
+        (Some("_denViews_dash"), Some("api")) => "dashboard_api",
+        (Some("_denViews_dash"), _) => "dashboard",
+        (Some("_denViews_flush"), _) => "flush",
+        (Some("_denViews_metrics"), _) => "metrics",
+        (Some("_denViews_badge"), _) => "badge",
+        (Some("_denViews_pixel.gif"), _) => "pixel",
+        (Some("_denViews_views"), _) => "views",
+        (Some(p), _) if super::script::is_script(p) => "script",
+        (Some("_denViews_healthz"), _)
+        | (Some("_denViews_readyz"), _)
+        | (Some("healthz"), _)
+        | (Some("readyz"), _) => "health",
+        _ => "track",
+    }
+}
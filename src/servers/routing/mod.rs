@@ -1,3 +1,18 @@
 pub mod api;
+pub mod auth;
+pub mod badge;
+pub mod batch;
+pub mod cors;
+pub mod csrf;
+pub mod exclude;
+pub mod health;
+pub mod identity;
+pub mod live;
+pub mod metrics;
+pub mod normalize;
+pub mod pixel;
+pub mod probe;
 pub mod response_utils;
+pub mod rules;
+pub mod script;
 pub mod tools;
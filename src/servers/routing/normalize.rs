@@ -0,0 +1,387 @@
+// normalize.rs
+//
+// Turns a request's path and query into the path that denViews stores.
+// Every route that reads or writes views goes through this, so that the
+// same page always ends up as the same record.
+//
+// The steps in the `path_normalization` setting run in the order given:
+//
+//     collapse_slashes       /a//b -> /a/b
+//     percent_decode         /caf%C3%A9 -> /café (but %2F stays as it is)
+//     lowercase              /About -> /about
+//     strip_html             /about.html -> /about
+//     strip_trailing_slash   /about/ -> /about
+//     rewrite                the rules in `path_rewrites`
+//
+// Rewrite rules are written as `regex => replacement`, and the
+// replacement can use the regex's groups ($1, $name). If `rewrite` isn't
+// in the list of steps, the rules run after everything else.
+//
+// Before any of that, `remove_index_pages` drops a final index.html.
+// Queries are dropped, unless `ignore_queries` is set, in which case they
+// are kept; if `query_params` isn't empty, only the parameters it names
+// are kept. Without `strip_trailing_slash`, /about/ and /about are kept
+// apart.
+
+use crate::database::DenViewSettings;
+use crate::Error;
+use hyper::Uri;
+use regex::Regex;
+
+#[derive(PartialEq)]
+enum Step {
+    CollapseSlashes,
+    PercentDecode,
+    Lowercase,
+    StripHtml,
+    StripTrailingSlash,
+    Rewrite,
+}
+
+impl Step {
+    fn parse(step: &str) -> Option<Self> {
+        Some(match step {
+            "collapse_slashes" => Step::CollapseSlashes,
+            "percent_decode" => Step::PercentDecode,
+            "lowercase" => Step::Lowercase,
+            "strip_html" => Step::StripHtml,
+            "strip_trailing_slash" => Step::StripTrailingSlash,
+            "rewrite" => Step::Rewrite,
+            _ => return None,
+        })
+    }
+}
+
+struct Rewrite {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl Rewrite {
+    fn parse(rule: &str) -> Result<Self, Error> {
+        let (pattern, replacement) = rule
+            .split_once("=>")
+            .ok_or("rewrite rules are written as `regex => replacement`")?;
+
+        Ok(Rewrite {
+            pattern: Regex::new(pattern.trim())?,
+            replacement: replacement.trim().into(),
+        })
+    }
+}
+
+pub struct Normalizer {
+    steps: Vec<Step>,
+    rewrites: Vec<Rewrite>,
+    remove_index_pages: bool,
+    keep_queries: bool,
+    query_params: Vec<String>,
+}
+
+impl Normalizer {
+    // Steps and rules that fail to parse are logged and skipped, rather
+    // than stopping denViews from starting.
+    pub fn new(settings: &DenViewSettings) -> Self {
+        let mut steps: Vec<Step> = settings
+            .path_normalization
+            .iter()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| match Step::parse(s) {
+                Some(s) => Some(s),
+                None => {
+                    log::error!("ignoring unknown normalization step {}", s);
+                    None
+                }
+            })
+            .collect();
+
+        if !steps.contains(&Step::Rewrite) {
+            steps.push(Step::Rewrite);
+        }
+
+        let rewrites = settings
+            .path_rewrites
+            .iter()
+            .filter(|r| !r.trim().is_empty())
+            .filter_map(|r| match Rewrite::parse(r) {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    log::error!("ignoring invalid rewrite rule {}: {}", r, e);
+                    None
+                }
+            })
+            .collect();
+
+        Normalizer {
+            steps,
+            rewrites,
+            remove_index_pages: settings.remove_index_pages,
+            keep_queries: settings.ignore_queries,
+            query_params: settings.query_params.clone(),
+        }
+    }
+
+    // normalize
+    //
+    // `path` is the request path, with its leading slash; the result
+    // doesn't have one, matching how paths are stored.
+    pub fn normalize(&self, path: &str, query: Option<&str>) -> String {
+        let mut path = path.to_string();
+
+        if self.remove_index_pages {
+            if let Some(p) = path.strip_suffix("index.html") {
+                if p.ends_with('/') {
+                    path.truncate(p.len());
+                }
+            }
+        }
+
+        for step in &self.steps {
+            path = match step {
+                Step::CollapseSlashes => collapse_slashes(&path),
+                Step::PercentDecode => decode(&path, true),
+                Step::Lowercase => path.to_lowercase(),
+                Step::StripHtml => path.strip_suffix(".html").unwrap_or(&path).to_string(),
+                Step::StripTrailingSlash => path.trim_end_matches('/').to_string(),
+                Step::Rewrite => self.rewrites.iter().fold(path, |p, r| {
+                    r.pattern.replace(&p, r.replacement.as_str()).into_owned()
+                }),
+            };
+        }
+
+        let path = path.trim_start_matches('/');
+
+        match self.query(query) {
+            Some(q) => format!("{}?{}", path, q),
+            None => path.to_string(),
+        }
+    }
+
+    fn query(&self, query: Option<&str>) -> Option<String> {
+        let query = query.filter(|q| !q.is_empty())?;
+
+        if !self.query_params.is_empty() {
+            let kept = query
+                .split('&')
+                .filter(|p| {
+                    let name = p.split('=').next().unwrap_or("");
+                    self.query_params.iter().any(|k| k == name)
+                })
+                .collect::<Vec<&str>>();
+
+            return match kept.is_empty() {
+                true => None,
+                false => Some(kept.join("&")),
+            };
+        }
+
+        match self.keep_queries {
+            true => Some(query.to_string()),
+            false => None,
+        }
+    }
+}
+
+// split
+//
+// Splits a page into the path (with a leading slash) and query that
+// Normalizer::normalize takes. The page can be a bare path
+// (/blog/post?page=2) or a whole URL; a fragment is dropped.
+pub fn split(page: &str) -> Result<(String, Option<String>), Error> {
+    let page = page.split('#').next().unwrap_or("");
+
+    if page.contains("://") {
+        let uri = page.parse::<Uri>()?;
+        return Ok((uri.path().to_string(), uri.query().map(|q| q.to_string())));
+    }
+
+    let (path, query) = match page.split_once('?') {
+        Some((p, q)) => (p, Some(q.to_string())),
+        None => (page, None),
+    };
+
+    Ok((String::from("/") + path.trim_start_matches('/'), query))
+}
+
+fn collapse_slashes(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !out.ends_with('/') {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+// Invalid escapes are left as they are, and anything that doesn't decode
+// to UTF-8 is replaced.
+pub fn percent_decode(s: &str) -> String {
+    decode(s, false)
+}
+
+// An escaped slash in a path is part of a segment, so it's kept escaped
+// when decoding a path; otherwise /a%2Fb would end up in folder a.
+fn decode(path: &str, keep_slashes: bool) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        // from_str_radix takes a leading sign, so check both digits first,
+        // or %+f would decode
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            // two ASCII hex digits are always valid UTF-8 and a valid byte
+            let b = u8::from_str_radix(&path[i + 1..i + 3], 16).unwrap();
+            if !(keep_slashes && b == b'/') {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalizer(steps: &[&str], rewrites: &[&str]) -> Normalizer {
+        Normalizer::new(&DenViewSettings {
+            path_normalization: steps.iter().map(|s| s.to_string()).collect(),
+            path_rewrites: rewrites.iter().map(|r| r.to_string()).collect(),
+            ..DenViewSettings::default()
+        })
+    }
+
+    #[test]
+    fn test_default_steps() {
+        let n = Normalizer::new(&DenViewSettings::default());
+        assert_eq!(n.normalize("/", None), "");
+        assert_eq!(n.normalize("/about/", None), "about");
+        assert_eq!(n.normalize("/About.html", None), "About.html");
+        assert_eq!(n.normalize("/a//b", None), "a//b");
+    }
+
+    #[test]
+    fn test_trailing_slash_is_optional() {
+        let n = normalizer(&[], &[]);
+        assert_eq!(n.normalize("/about/", None), "about/");
+        assert_eq!(n.normalize("/about", None), "about");
+        assert_eq!(n.normalize("/", None), "");
+
+        let n = normalizer(&["strip_trailing_slash"], &[]);
+        assert_eq!(n.normalize("/about//", None), "about");
+    }
+
+    #[test]
+    fn test_steps_run_in_order() {
+        let n = normalizer(
+            &[
+                "collapse_slashes",
+                "lowercase",
+                "strip_html",
+                "strip_trailing_slash",
+            ],
+            &[],
+        );
+        assert_eq!(n.normalize("//Blog//Post.HTML", None), "blog/post");
+
+        // .HTML isn't stripped until after it's lowercased
+        let n = normalizer(&["strip_html", "lowercase"], &[]);
+        assert_eq!(n.normalize("/Post.HTML", None), "post.html");
+    }
+
+    #[test]
+    fn test_percent_decode_step() {
+        let n = normalizer(&["percent_decode"], &[]);
+        assert_eq!(n.normalize("/caf%C3%A9", None), "café");
+        assert_eq!(n.normalize("/a%2Fb", None), "a%2Fb");
+        assert_eq!(n.normalize("/a%2fb", None), "a%2fb");
+        assert_eq!(n.normalize("/100%", None), "100%");
+        assert_eq!(n.normalize("/%zz", None), "%zz");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("%2Fblog%2Fpost"), "/blog/post");
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%41%4"), "A%4");
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+        assert_eq!(percent_decode("%+f%-0%2x"), "%+f%-0%2x");
+    }
+
+    #[test]
+    fn test_rewrites() {
+        let n = normalizer(&[], &[r"^/blog/[0-9]{4}/(.*)$ => /blog/$1", "not a rule"]);
+        assert_eq!(n.normalize("/blog/2021/post", None), "blog/post");
+
+        // rewrites run where they're listed, or last
+        let n = normalizer(&["rewrite", "lowercase"], &["^/A$ => /B"]);
+        assert_eq!(n.normalize("/A", None), "b");
+        let n = normalizer(&["lowercase"], &["^/A$ => /B"]);
+        assert_eq!(n.normalize("/A", None), "a");
+    }
+
+    #[test]
+    fn test_index_pages_and_queries() {
+        let n = Normalizer::new(&DenViewSettings {
+            remove_index_pages: true,
+            ..DenViewSettings::default()
+        });
+        assert_eq!(n.normalize("/docs/index.html", None), "docs");
+        assert_eq!(n.normalize("/index.html", None), "");
+        assert_eq!(
+            n.normalize("/docs/notindex.html", None),
+            "docs/notindex.html"
+        );
+
+        let dropped = Normalizer::new(&DenViewSettings {
+            ignore_queries: false,
+            ..DenViewSettings::default()
+        });
+        assert_eq!(dropped.normalize("/a", Some("x=1")), "a");
+
+        let kept = Normalizer::new(&DenViewSettings {
+            ignore_queries: true,
+            ..DenViewSettings::default()
+        });
+        assert_eq!(kept.normalize("/a", Some("x=1")), "a?x=1");
+        assert_eq!(kept.normalize("/a", Some("")), "a");
+
+        let some = Normalizer::new(&DenViewSettings {
+            query_params: vec!["page".into()],
+            ..DenViewSettings::default()
+        });
+        assert_eq!(some.normalize("/a", Some("utm=x&page=2")), "a?page=2");
+        assert_eq!(some.normalize("/a", Some("utm=x")), "a");
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("/blog/post?page=2#top").unwrap(),
+            ("/blog/post".to_string(), Some("page=2".to_string()))
+        );
+        assert_eq!(split("blog").unwrap(), ("/blog".to_string(), None));
+        assert_eq!(
+            split("https://example.com/a/b?c=d").unwrap(),
+            ("/a/b".to_string(), Some("c=d".to_string()))
+        );
+        assert_eq!(
+            split("https://example.com").unwrap(),
+            ("/".to_string(), None)
+        );
+    }
+}
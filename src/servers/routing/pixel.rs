@@ -0,0 +1,62 @@
+// pixel.rs
+//
+// A 1x1 transparent GIF that records a hit when it's loaded, for pages
+// (and RSS readers, and newsletters) that can't run a script. The page
+// is either given in the `path` parameter, or taken from the Referer.
+
+use super::normalize;
+use crate::error::ApiError;
+use crate::Error;
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE, EXPIRES, PRAGMA, REFERER},
+    Body, Request, Response,
+};
+
+const GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct PixelQuery {
+    pub path: Option<String>,
+}
+
+// page
+//
+// The path and query of the page being viewed. `path` can be a bare
+// path (/blog/post?page=2) or a whole URL; either wins over the Referer,
+// which browsers often cut down to just the origin.
+pub fn page(req: &Request<Body>) -> Result<(String, Option<String>), Error> {
+    let query = serde_qs::from_str::<PixelQuery>(req.uri().query().unwrap_or(""))?;
+
+    let page = match query.path.filter(|p| !p.is_empty()) {
+        Some(p) => p,
+        None => match req.headers().get(REFERER).and_then(|r| r.to_str().ok()) {
+            Some(r) => r.to_string(),
+            None => {
+                return Err(Box::new(ApiError::BadRequest(
+                    "no path was given, and there is no Referer".into(),
+                )))
+            }
+        },
+    };
+
+    normalize::split(&page)
+}
+
+// Nothing in between is allowed to keep the pixel, or views served from
+// a cache would never be seen.
+pub fn response() -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "image/gif")
+        .header(
+            CACHE_CONTROL,
+            "no-cache, no-store, must-revalidate, max-age=0",
+        )
+        .header(PRAGMA, "no-cache")
+        .header(EXPIRES, "0")
+        .body(Body::from(GIF))?)
+}
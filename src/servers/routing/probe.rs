@@ -0,0 +1,180 @@
+// probe.rs
+//
+// Checks that a path exists by requesting it from the tracked site.
+// Results are cached per normalized path, for `check_cache_ttl` seconds if
+// the page exists and `check_cache_negative_ttl` seconds if it doesn't, so
+// that the site is asked at most once in a while rather than on every
+// view. Requests for a path that's already being checked wait on that
+// check instead of starting their own. A site that couldn't be reached
+// says nothing about the page, so that's never cached past the check.
+
+use crate::database::DenViewSettings;
+use hyper::{client::HttpConnector, header::LOCATION, Body, Client, Uri};
+use hyper_rustls::HttpsConnector;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+use tokio_rustls::rustls::ClientConfig;
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+// http_client
+//
+// The client shared by everything in denViews that makes outbound
+// requests. Connecting gives up after `check_connect_timeout` seconds;
+// reading is bounded by whoever uses it (see with_timeout).
+pub fn http_client(settings: &DenViewSettings) -> HttpClient {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(Duration::from_secs(settings.check_connect_timeout)));
+
+    let mut tls = ClientConfig::new();
+    tls.root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Client::builder().build::<_, Body>(HttpsConnector::from((http, tls)))
+}
+
+pub async fn with_timeout<T, E: std::fmt::Display>(
+    timeout: Duration,
+    fut: impl Future<Output = Result<T, E>>,
+) -> Result<T, String> {
+    match tokio::time::timeout(timeout, fut).await {
+        Err(_) => Err("timed out".into()),
+        Ok(r) => r.map_err(|e| e.to_string()),
+    }
+}
+
+// (exists, reason it doesn't), when it was checked
+type CheckResult = ((bool, String), Instant);
+
+pub struct SiteProbe {
+    client: HttpClient,
+    read_timeout: Duration,
+    ttl: Duration,
+    negative_ttl: Duration,
+    max_entries: usize,
+    cache: Mutex<HashMap<String, Arc<OnceCell<CheckResult>>>>,
+}
+
+impl SiteProbe {
+    pub fn new(settings: &DenViewSettings, client: HttpClient) -> Self {
+        SiteProbe {
+            client,
+            read_timeout: Duration::from_secs(settings.check_read_timeout),
+            ttl: Duration::from_secs(settings.check_cache_ttl),
+            negative_ttl: Duration::from_secs(settings.check_cache_negative_ttl),
+            max_entries: settings.check_cache_size,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Whether a probe built from `old` would still give the same answers
+    // under `new`, so that its cache can be kept.
+    pub fn unchanged(old: &DenViewSettings, new: &DenViewSettings) -> bool {
+        old.site == new.site
+            && old.use_https == new.use_https
+            && old.check_connect_timeout == new.check_connect_timeout
+            && old.check_read_timeout == new.check_read_timeout
+            && old.check_cache_ttl == new.check_cache_ttl
+            && old.check_cache_negative_ttl == new.check_cache_negative_ttl
+            && old.check_cache_size == new.check_cache_size
+    }
+
+    fn expired(&self, result: &CheckResult) -> bool {
+        let ttl = match &result.0 {
+            (true, _) => self.ttl,
+            (false, reason) if reason.is_empty() => self.negative_ttl,
+            (false, _) => return true,
+        };
+
+        result.1.elapsed() >= ttl
+    }
+
+    // Gets the cache slot for a path, making a new one if there isn't one
+    // or the old result has expired. A slot that's still being checked
+    // is always handed out as-is.
+    fn slot(&self, key: &str) -> Arc<OnceCell<CheckResult>> {
+        let mut cache = self.cache.lock().unwrap();
+
+        if let Some(slot) = cache.get(key) {
+            match slot.get() {
+                Some(r) if self.expired(r) => (),
+                _ => return slot.clone(),
+            }
+        }
+
+        if cache.len() >= self.max_entries {
+            cache.retain(|_, s| !matches!(s.get(), Some(r) if self.expired(r)));
+        }
+
+        // still full: drop the oldest finished check
+        if cache.len() >= self.max_entries {
+            let oldest = cache
+                .iter()
+                .filter_map(|(k, s)| s.get().map(|r| (k, r.1)))
+                .min_by_key(|(_, t)| *t)
+                .map(|(k, _)| k.clone());
+
+            if let Some(k) = oldest {
+                cache.remove(&k);
+            }
+        }
+
+        let slot = Arc::new(OnceCell::new());
+        if self.max_entries > 0 {
+            cache.insert(key.to_string(), slot.clone());
+        }
+
+        slot
+    }
+
+    // check
+    //
+    // Whether the page at `uri` exists, cached under `key`.
+    pub async fn check(&self, key: &str, uri: Uri) -> (bool, String) {
+        let slot = self.slot(key);
+        let result = slot
+            .get_or_init(|| async { (self.request(uri).await, Instant::now()) })
+            .await;
+
+        result.0.clone()
+    }
+
+    async fn request(&self, uri: Uri) -> (bool, String) {
+        log::info!("checking {:?}", uri);
+
+        let res = match with_timeout(self.read_timeout, self.client.get(uri)).await {
+            Err(e) => return (false, e),
+            Ok(r) => r,
+        };
+        log::debug!("{:?}", res);
+
+        // only a single layer down into the redirect,
+        // to avoid any fuckery
+        if res.status().is_redirection() {
+            let redirect = match res
+                .headers()
+                .get(LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| l.parse::<Uri>().ok())
+            {
+                None => return (false, "redirect without a valid location".into()),
+                Some(r) => r,
+            };
+
+            log::info!("redirected, checking {}", redirect);
+            return match with_timeout(self.read_timeout, self.client.get(redirect)).await {
+                Err(e) => (false, e),
+                Ok(r) => (r.status().is_success(), "".into()),
+            };
+        }
+
+        (res.status().is_success(), "".into())
+    }
+}
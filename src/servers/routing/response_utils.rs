@@ -1,3 +1,6 @@
+// Shorthands for common responses. The error ones all go through
+// crate::error::ApiError, so that they're sent back as JSON.
+
 #[macro_export]
 macro_rules! ok {
     () => {
@@ -11,32 +14,25 @@ macro_rules! ok {
 pub(crate) use ok;
 
 #[macro_export]
-macro_rules! internal_error {
+macro_rules! malformed {
     () => {
-        $crate::response_with_code!(500, "an error occurred during processing")
-    };
-
-    ($err:expr) => {
-        $crate::response_with_code!(
-            500,
-            format!("an error occurred during processing: {}", $err)
-        )
+        $crate::error::ApiError::BadRequest("malformed request".into()).response()
     };
 }
-pub(crate) use internal_error;
+pub(crate) use malformed;
 
 #[macro_export]
-macro_rules! malformed {
+macro_rules! forbidden {
     () => {
-        $crate::response_with_code!(400, "malformed request")
+        $crate::error::ApiError::Forbidden.response()
     };
 }
-pub(crate) use malformed;
+pub(crate) use forbidden;
 
 #[macro_export]
 macro_rules! not_found {
     () => {
-        $crate::response_with_code!(404, "not found")
+        $crate::error::ApiError::NotFound("not found".into()).response()
     };
 }
 pub(crate) use not_found;
@@ -62,11 +58,7 @@ pub(crate) use response_with_code;
 #[macro_export]
 macro_rules! request_auth {
     () => {
-        hyper::Response::builder()
-            .status(401)
-            .header(hyper::header::WWW_AUTHENTICATE, "Basic")
-            .body(hyper::Body::from("not authorized"))
-            .unwrap()
+        $crate::error::ApiError::Unauthorized.response()
     };
 }
 pub(crate) use request_auth;
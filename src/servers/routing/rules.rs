@@ -0,0 +1,292 @@
+// rules.rs
+//
+// Path allowlist rules, as an alternative to asking the tracked site
+// whether a page exists. A path is allowed if it matches any rule in the
+// `path_rules` setting, or if it is listed in the configured sitemap.
+//
+// Rules are written as `kind:pattern`, where kind is one of:
+//
+//     prefix:/blog/         paths starting with /blog/
+//     glob:/posts/*.html    a glob over the whole path; `*` stays within
+//                           one segment, and `/**/` spans any number
+//     regex:^/p/[0-9]+$     a regular expression over the whole path
+//
+// A rule without a kind is a prefix.
+
+use super::probe::{with_timeout, HttpClient};
+use crate::database::DenViewSettings;
+use crate::Error;
+use glob::{MatchOptions, Pattern};
+use hyper::{body::to_bytes, Uri};
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, RwLock};
+
+const SITEMAP_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum Rule {
+    Prefix(String),
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+impl Rule {
+    pub fn parse(rule: &str) -> Result<Self, Error> {
+        Ok(match rule.split_once(':') {
+            Some(("prefix", p)) => Rule::Prefix(p.into()),
+            Some(("glob", p)) => Rule::Glob(Pattern::new(p)?),
+            Some(("regex", p)) => Rule::Regex(Regex::new(p)?),
+            _ => Rule::Prefix(rule.into()),
+        })
+    }
+
+    // Parses every non-empty rule in a setting. Rules that fail to parse
+    // are logged and skipped, rather than stopping denViews from starting.
+    pub fn parse_list(rules: &[String]) -> Vec<Self> {
+        rules
+            .iter()
+            .map(|r| r.trim())
+            .filter(|r| !r.is_empty())
+            .filter_map(|r| match Rule::parse(r) {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    log::error!("ignoring invalid rule {}: {}", r, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Rule::Prefix(p) => path.starts_with(p.as_str()),
+            Rule::Glob(p) => p.matches_with(
+                path,
+                MatchOptions {
+                    case_sensitive: true,
+                    require_literal_separator: true,
+                    require_literal_leading_dot: false,
+                },
+            ),
+            Rule::Regex(r) => r.is_match(path),
+        }
+    }
+}
+
+struct Sitemap {
+    paths: HashSet<String>,
+    loaded: Option<Instant>,
+}
+
+pub struct PathRules {
+    rules: Vec<Rule>,
+    sitemap_source: Option<String>,
+    sitemap_refresh: Duration,
+    sitemap: RwLock<Sitemap>,
+    // held while the sitemap is being fetched, so that only one request
+    // fetches it at a time
+    loading: Mutex<()>,
+    client: HttpClient,
+}
+
+impl PathRules {
+    pub fn new(settings: &DenViewSettings, client: HttpClient) -> Self {
+        let rules = Rule::parse_list(&settings.path_rules);
+
+        let sitemap_source = match settings.sitemap.trim() {
+            "" => None,
+            s => Some(s.to_string()),
+        };
+
+        PathRules {
+            rules,
+            sitemap_source,
+            sitemap_refresh: Duration::from_secs(settings.sitemap_refresh),
+            sitemap: RwLock::new(Sitemap {
+                paths: HashSet::new(),
+                loaded: None,
+            }),
+            loading: Mutex::new(()),
+            client,
+        }
+    }
+
+    // Whether rules built from `old` are the same under `new`, so that the
+    // sitemap that's already loaded can be kept.
+    pub fn unchanged(old: &DenViewSettings, new: &DenViewSettings) -> bool {
+        old.path_rules == new.path_rules
+            && old.sitemap == new.sitemap
+            && old.sitemap_refresh == new.sitemap_refresh
+            && old.check_connect_timeout == new.check_connect_timeout
+    }
+
+    // allows
+    //
+    // `page` is the path as it was requested, before it's normalized, so
+    // that rules and the sitemap match the site's own URLs. Queries are
+    // never part of a rule match.
+    pub async fn allows(&self, page: &str) -> bool {
+        let path = page.split('?').next().unwrap_or("");
+
+        if self.rules.iter().any(|r| r.matches(path)) {
+            return true;
+        }
+
+        match self.sitemap_source {
+            None => false,
+            Some(_) => {
+                self.refresh_sitemap().await;
+                self.sitemap.read().await.paths.contains(trim_path(path))
+            }
+        }
+    }
+
+    // Reloads the sitemap if it has never been loaded, or is older than
+    // `sitemap_refresh`. If loading fails, the old list is kept.
+    //
+    // The list is only locked to swap in the new one, not while it's
+    // fetched. Until the first load, requests wait for it; after that,
+    // they keep using the old list while one of them fetches the new one.
+    async fn refresh_sitemap(&self) {
+        let source = match &self.sitemap_source {
+            None => return,
+            Some(s) => s,
+        };
+
+        let loaded = self.sitemap.read().await.loaded;
+        let _loading = match loaded {
+            Some(t) if t.elapsed() < self.sitemap_refresh => return,
+            Some(_) => match self.loading.try_lock() {
+                Ok(l) => l,
+                Err(_) => return,
+            },
+            None => self.loading.lock().await,
+        };
+
+        // someone else may have refreshed it while we waited for the lock
+        let loaded = self.sitemap.read().await.loaded;
+        if let Some(t) = loaded {
+            if t.elapsed() < self.sitemap_refresh {
+                return;
+            }
+        }
+
+        let loaded = self.load_sitemap(source).await;
+        let mut sitemap = self.sitemap.write().await;
+        match loaded {
+            Ok(paths) => {
+                log::info!("loaded {} paths from sitemap {}", paths.len(), source);
+                sitemap.paths = paths;
+            }
+            Err(e) => log::error!("could not load sitemap {}: {}", source, e),
+        }
+
+        // failures also wait out the refresh period, so that a broken
+        // sitemap isn't fetched again on every request
+        sitemap.loaded = Some(Instant::now());
+    }
+
+    async fn load_sitemap(&self, source: &str) -> Result<HashSet<String>, Error> {
+        let xml = match source.starts_with("http://") || source.starts_with("https://") {
+            true => {
+                let res = with_timeout(
+                    SITEMAP_FETCH_TIMEOUT,
+                    self.client.get(source.parse::<Uri>()?),
+                )
+                .await?;
+
+                if !res.status().is_success() {
+                    return Err(format!("sitemap returned {}", res.status()).into());
+                }
+
+                let body = with_timeout(SITEMAP_FETCH_TIMEOUT, to_bytes(res.into_body())).await?;
+                String::from_utf8(body.to_vec())?
+            }
+            false => tokio::fs::read_to_string(source).await?,
+        };
+
+        Ok(sitemap_paths(&xml))
+    }
+}
+
+// Gets the path of every <loc> in a sitemap. Sitemap locations are full
+// URLs, but only the path is compared.
+fn sitemap_paths(xml: &str) -> HashSet<String> {
+    let loc = Regex::new(r"<loc>\s*([^<]*?)\s*</loc>").unwrap();
+
+    loc.captures_iter(xml)
+        .filter_map(|c| c[1].replace("&amp;", "&").parse::<Uri>().ok())
+        .map(|u| trim_path(u.path()).to_string())
+        .collect()
+}
+
+fn trim_path(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        p => p,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(rule: &str, path: &str) -> bool {
+        Rule::parse(rule).unwrap().matches(path)
+    }
+
+    #[test]
+    fn test_rule_kinds() {
+        assert!(matches("prefix:/blog/", "/blog/post"));
+        assert!(!matches("prefix:/blog/", "/blog"));
+        assert!(matches("/blog", "/blogroll"));
+        assert!(matches("regex:^/p/[0-9]+$", "/p/12"));
+        assert!(!matches("regex:^/p/[0-9]+$", "/p/12/edit"));
+        assert!(Rule::parse("regex:(").is_err());
+        assert!(Rule::parse("glob:/a/[").is_err());
+    }
+
+    #[test]
+    fn test_glob_segments() {
+        assert!(matches("glob:/posts/*.html", "/posts/a.html"));
+        assert!(!matches("glob:/posts/*.html", "/posts/2021/a.html"));
+        assert!(matches("glob:/posts/**/*.html", "/posts/a.html"));
+        assert!(matches("glob:/posts/**/*.html", "/posts/2021/05/a.html"));
+        assert!(!matches("glob:/posts/**/*.html", "/other/a.html"));
+        assert!(matches("glob:/docs/**", "/docs/a/b"));
+        assert!(matches("glob:/p/?", "/p/1"));
+        assert!(!matches("glob:/p/?", "/p/"));
+        assert!(!matches("glob:/Posts/*", "/posts/a"));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let rules = Rule::parse_list(&[
+            " prefix:/a ".into(),
+            "".into(),
+            "regex:(".into(),
+            "glob:/b/*".into(),
+        ]);
+        assert_eq!(rules.len(), 2);
+    }
+
+    #[test]
+    fn test_sitemap_paths() {
+        let xml = "
+            <urlset>
+                <url><loc> https://example.com/ </loc></url>
+                <url><loc>https://example.com/About.html</loc></url>
+                <url><loc>https://example.com/blog/</loc></url>
+                <url><loc>https://example.com/search?q=a&amp;b=c</loc></url>
+            </urlset>
+        ";
+        let paths = sitemap_paths(xml);
+        assert_eq!(paths.len(), 4);
+        for p in ["/", "/About.html", "/blog", "/search"].iter() {
+            assert!(paths.contains(*p), "{}", p);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// script.rs
+//
+// Serves the tracking script (see crate::tracker). The stable URL is
+// cached for an hour and revalidated through its ETag, so sites pick up
+// a new script soon after an upgrade; the hashed URL never changes, and
+// is cached for as long as anything will keep it. A hash that isn't the
+// current one is sent to the stable URL, so pinned scripts keep working
+// across upgrades.
+
+use crate::error::ApiError;
+use crate::tracker;
+use crate::Error;
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH, LOCATION},
+    Body, Request, Response,
+};
+
+pub const STABLE: &str = "_denViews_script.js";
+
+// Whether a path segment is the script, stable or hashed.
+pub fn is_script(name: &str) -> bool {
+    name.starts_with("_denViews_script.") && name.ends_with(".js")
+}
+
+pub fn response(req: &Request<Body>, name: &str) -> Result<Response<Body>, Error> {
+    let script = match tracker::get_script() {
+        Some(s) => s,
+        None => {
+            return Err(Box::new(ApiError::Internal(
+                "tracking script is missing".into(),
+            )))
+        }
+    };
+    let etag = format!("\"{}\"", script.hash);
+
+    if name == STABLE {
+        let cached = req
+            .headers()
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(',').any(|t| t.trim() == etag))
+            .unwrap_or(false);
+
+        let res = Response::builder()
+            .header(CACHE_CONTROL, "public, max-age=3600")
+            .header(ETAG, &etag);
+
+        return Ok(match cached {
+            true => res.status(304).body(Body::from(""))?,
+            false => res
+                .header(CONTENT_TYPE, "text/javascript; charset=utf-8")
+                .body(Body::from(script.data))?,
+        });
+    }
+
+    if name == format!("_denViews_script.{}.js", script.hash) {
+        return Ok(Response::builder()
+            .header(CONTENT_TYPE, "text/javascript; charset=utf-8")
+            .header(CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .header(ETAG, &etag)
+            .body(Body::from(script.data))?);
+    }
+
+    Ok(Response::builder()
+        .status(302)
+        .header(LOCATION, format!("/{}", STABLE))
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(""))?)
+}
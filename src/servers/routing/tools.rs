@@ -1,9 +1,16 @@
+use super::auth::{self, Auth};
+use super::csrf;
 use super::response_utils;
 use crate::dashboard;
 use crate::database::{DatabaseTool, DenViewSettings, *};
+use crate::error::{self, ApiError};
 use crate::Error;
-use hyper::{body::to_bytes, Body, Method, Request, Response, Uri};
-use std::sync::Arc;
+use hyper::{
+    body::to_bytes,
+    header::{LOCATION, SET_COOKIE},
+    Body, Method, Request, Response, Uri,
+};
+use std::{net::IpAddr, sync::Arc};
 
 pub struct ToolsHandler<T> {
     tools: Arc<T>,
@@ -20,6 +27,52 @@ struct FolderQuery {
     folder_id: u32,
 }
 
+// Anything left out stays as it is.
+#[derive(serde::Deserialize)]
+struct MovePageForm {
+    to_folder_id: Option<u32>,
+    to_name: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct MoveFolderForm {
+    to_parent_id: Option<u32>,
+    to_name: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct MergeForm {
+    into_folder_id: u32,
+    into_name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct LoginForm {
+    user: String,
+    pass: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CredentialsForm {
+    current_pass: String,
+    #[serde(default)]
+    user: String,
+    pass: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenQuery {
+    token_id: i32,
+}
+
+// The only time a token is ever shown in plaintext.
+#[derive(serde::Serialize)]
+struct CreatedToken {
+    token: String,
+    #[serde(flatten)]
+    record: ApiTokenRecord,
+}
+
 impl<T: DatabaseTool> ToolsHandler<T> {
     pub fn new(tools: T) -> Self {
         ToolsHandler {
@@ -31,19 +84,159 @@ impl<T: DatabaseTool> ToolsHandler<T> {
         self.tools.check().await
     }
 
+    pub async fn schema_version(&self) -> Result<Option<i64>, Error> {
+        self.tools.schema_version().await
+    }
+
+    pub fn pool_status(&self) -> PoolStatus {
+        self.tools.pool_status()
+    }
+
+    pub async fn close(&self) {
+        self.tools.close().await
+    }
+
+    pub async fn upgrade(&self) -> Result<(), Error> {
+        self.tools.upgrade().await
+    }
+
     pub async fn auth(&self, user: String, pass: String) -> Result<bool, Error> {
         self.tools.auth(user, pass).await
     }
 
-    pub async fn handle(&self, req: Request<Body>) -> Result<Response<Body>, Error> {
-        let pq = req.uri().path_and_query().unwrap();
-        let path = pq.path()[1..]
-            .split('/')
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
+    pub async fn check_token(&self, token_hash: String) -> Result<Option<ApiTokenRecord>, Error> {
+        self.tools.check_token(token_hash).await
+    }
+
+    pub async fn check_session(
+        &self,
+        session_hash: String,
+        idle_timeout: u64,
+        max_age: u64,
+    ) -> Result<Option<SessionRecord>, Error> {
+        self.tools
+            .check_session(session_hash, idle_timeout, max_age)
+            .await
+    }
+
+    // audit
+    //
+    // Failures here are logged instead of returned: by the time an action
+    // is audited, it has already happened.
+    pub async fn audit(&self, entry: AuditEntry) {
+        if let Err(e) = self.tools.append_audit(entry).await {
+            log::error!("could not write to the audit log: {}", e);
+        }
+    }
+
+    // handle_login
+    //
+    // Everything an unauthenticated request can reach in the dashboard:
+    // the login page, its assets, and the login route itself. Dashboard pages
+    // send the browser to the login page instead, while API routes still ask
+    // for Basic auth so that scripts keep working.
+    pub async fn handle_login(
+        &self,
+        req: Request<Body>,
+        settings: &DenViewSettings,
+        ip: IpAddr,
+    ) -> Result<Response<Body>, Error> {
+        let path = dash_path(&req);
+
+        Ok(
+            match (req.method(), path.get(1).map(|p| p.as_str()), path.len()) {
+                (&Method::POST, Some("api"), 3) if path[2] == "login" => {
+                    match csrf::same_origin(&req) {
+                        true => self.login(req, settings, ip).await?,
+                        false => response_utils::forbidden!(),
+                    }
+                }
+                (_, Some("api"), _) => response_utils::request_auth!(),
+                (&Method::GET, None, _)
+                | (&Method::GET, Some(""), _)
+                | (&Method::GET, Some("dash"), _)
+                | (&Method::GET, Some("settings"), _)
+                | (&Method::GET, Some("tokens"), _)
+                | (&Method::GET, Some("credentials"), _)
+                | (&Method::GET, Some("init"), _) => Response::builder()
+                    .status(303)
+                    .header(LOCATION, "/_denViews_dash/login")
+                    .body(Body::from(""))?,
+                (&Method::GET, Some(_), _) if path.len() <= 4 => {
+                    let resource = match path[1..].join("/").as_str() {
+                        "login" => "login.html".to_string(),
+                        r => r.to_string(),
+                    };
+
+                    match resource.ends_with(".html") && resource != "login.html" {
+                        true => response_utils::request_auth!(),
+                        false => match dashboard::get_resource(&resource) {
+                            Some(p) => response_utils::ok!(p),
+                            None => response_utils::not_found!(),
+                        },
+                    }
+                }
+                _ => response_utils::request_auth!(),
+            },
+        )
+    }
+
+    async fn login(
+        &self,
+        mut req: Request<Body>,
+        settings: &DenViewSettings,
+        ip: IpAddr,
+    ) -> Result<Response<Body>, Error> {
+        let form = match serde_qs::from_bytes::<LoginForm>(&to_bytes(req.body_mut()).await?) {
+            Err(_) => return Ok(response_utils::malformed!()),
+            Ok(v) => v,
+        };
+
+        if !self.tools.auth(form.user.clone(), form.pass).await? {
+            self.audit(AuditEntry::new(
+                "anonymous".into(),
+                AuditAction::LoginFailed,
+                ip,
+            ))
+            .await;
+
+            return Ok(Response::builder()
+                .status(303)
+                .header(LOCATION, "/_denViews_dash/login?failed=true")
+                .body(Body::from(""))?);
+        }
+
+        let session = util::create_token();
+        self.tools
+            .create_session(
+                util::hash_token(&session),
+                form.user.clone(),
+                util::create_token(),
+            )
+            .await?;
+        self.audit(AuditEntry::new(form.user, AuditAction::Login, ip))
+            .await;
+
+        Ok(Response::builder()
+            .status(303)
+            .header(LOCATION, "/_denViews_dash/dash")
+            .header(
+                SET_COOKIE,
+                auth::set_session_cookie(&session, settings.session_max_age),
+            )
+            .body(Body::from(""))?)
+    }
+
+    pub async fn handle(
+        &self,
+        req: Request<Body>,
+        auth: &Auth,
+        ip: IpAddr,
+    ) -> Result<Response<Body>, Error> {
+        let path = dash_path(&req);
 
         if path[0] != "_denViews_dash" {
-            return Ok(response_utils::response_with_code!(401, "unauthorized"));
+            return Ok(response_utils::not_found!());
         }
 
         if path.len() < 2 {
@@ -64,7 +257,7 @@ impl<T: DatabaseTool> ToolsHandler<T> {
             (&Method::GET, p) => match p {
                 "api" => match path.len() < 3 {
                     true => response_utils::not_found!(),
-                    false => self.db_op(req, &path[2]).await?,
+                    false => self.db_op(req, &path[2], auth, ip).await?,
                 },
                 "" => Response::builder()
                     .status(301)
@@ -76,7 +269,7 @@ impl<T: DatabaseTool> ToolsHandler<T> {
             (&Method::POST, p) => match p {
                 "api" => match path.len() < 3 {
                     true => response_utils::not_found!(),
-                    false => self.db_op(req, &path[2]).await?,
+                    false => self.db_op(req, &path[2], auth, ip).await?,
                 },
 
                 _ => response_utils::not_found!(),
@@ -85,31 +278,31 @@ impl<T: DatabaseTool> ToolsHandler<T> {
             (&Method::DELETE, p) => match p {
                 "api" => match path.len() < 3 {
                     true => response_utils::not_found!(),
-                    false => self.db_op(req, &path[2]).await?,
+                    false => self.db_op(req, &path[2], auth, ip).await?,
                 },
 
                 _ => response_utils::not_found!(),
             },
 
-            _ => response_utils::response_with_code!(405, "not allowed"),
+            _ => ApiError::MethodNotAllowed.response(),
         })
     }
 
     async fn get_resource(&self, page_route: &str) -> Result<Response<Body>, Error> {
         Ok(match page_route {
             "init" => match self.tools.check().await? {
-                true => {
-                    response_utils::response_with_code!(401, "denViews is already initialized.")
-                }
-                false => response_utils::ok!(dashboard::get_resource(
-                    &[page_route, "html"].join(".")
-                )
-                .unwrap()),
+                true => ApiError::Conflict("denViews is already initialized".into()).response(),
+                false => match dashboard::get_resource(&[page_route, "html"].join(".")) {
+                    Some(p) => response_utils::ok!(p),
+                    None => response_utils::not_found!(),
+                },
             },
-            "dash" | "settings" => response_utils::ok!(dashboard::get_resource(
-                &[page_route, "html"].join(".")
-            )
-            .unwrap()),
+            "dash" | "settings" | "tokens" | "credentials" => {
+                match dashboard::get_resource(&[page_route, "html"].join(".")) {
+                    Some(p) => response_utils::ok!(p),
+                    None => response_utils::not_found!(),
+                }
+            }
             _ => match dashboard::get_resource(page_route) {
                 Some(p) => response_utils::ok!(p),
                 None => response_utils::not_found!(),
@@ -121,88 +314,444 @@ impl<T: DatabaseTool> ToolsHandler<T> {
         &self,
         mut req: Request<Body>,
         api_route: &str,
+        auth: &Auth,
+        ip: IpAddr,
     ) -> Result<Response<Body>, Error> {
         log::info!(
             "running through route {} (method: {:?}) now",
             api_route,
             req.method()
         );
+
+        let scope = match (req.method(), api_route) {
+            (&Method::GET, "page")
+            | (&Method::GET, "folder")
+            | (&Method::GET, "history")
+            | (&Method::GET, "tree")
+            | (&Method::GET, "search") => TokenScope::ReadStats,
+            _ => TokenScope::Admin,
+        };
+
+        if !auth.allows(scope) {
+            return Ok(response_utils::forbidden!());
+        }
+
+        if req.method() != Method::GET && !csrf::verify(&mut req, auth).await? {
+            log::warn!(
+                "rejecting {} on {}: failed CSRF check",
+                req.method(),
+                api_route
+            );
+            return Ok(response_utils::forbidden!());
+        }
+
         Ok(match (req.method(), api_route) {
             (&Method::GET, "init") => match self.tools.check().await? {
-                true => response_utils::internal_error!("denViews is already initialized."),
+                true => ApiError::Conflict("denViews is already initialized".into()).response(),
                 false => response_utils::ok!(serde_json::to_string(&DenViewInit::default())?),
             },
-            (&Method::POST, "init") => {
-                match self.tools.check().await? {
-                    false => {
-                        let res: Result<DenViewInit, serde_qs::Error> =
-                            serde_qs::from_bytes(&to_bytes(req.body_mut()).await?);
-                        let settings = match res {
-                            Err(e) => return Ok(response_utils::internal_error!(e)),
-                            Ok(v) => v,
-                        };
-
-                        log::debug!("{:?}", settings);
-                        match self.tools.init(settings).await {
-                            Ok(_) => response_utils::ok!("denViews successfully initialized. Restart denViews to track sites."),
-                            Err(e) => response_utils::internal_error!(e)
+            (&Method::POST, "init") => match self.tools.check().await? {
+                false => {
+                    let res: Result<DenViewInit, serde_qs::Error> =
+                        serde_qs::from_bytes(&to_bytes(req.body_mut()).await?);
+                    let settings = match res {
+                        Err(e) => return Ok(error::response(Box::new(e))),
+                        Ok(v) => v,
+                    };
+
+                    log::debug!("{:?}", settings);
+                    let after = serde_json::to_value(DenViewSettings::from(settings.clone()))?;
+                    match self.tools.init(settings).await {
+                        Ok(_) => {
+                            self.audit(AuditEntry {
+                                after: Some(after),
+                                ..AuditEntry::new(auth.actor(), AuditAction::Init, ip)
+                            })
+                            .await;
+                            response_utils::ok!(
+                                "denViews successfully initialized, and is now tracking."
+                            )
                         }
+                        Err(e) => error::response(e),
                     }
-                    true => Response::new(Body::from("denViews is already initalized.")),
                 }
-            }
+                true => ApiError::Conflict("denViews is already initialized".into()).response(),
+            },
 
             (&Method::GET, "page") => match query_to_struct::<PageQuery>(req.uri()) {
                 None => response_utils::malformed!(),
-                Some(v) => match &self.tools.get_page(v.folder_id as i32, v.name).await {
-                    Ok(v) => response_utils::ok!(serde_json::to_string(v)?),
-                    Err(e) => response_utils::internal_error!(e),
+                Some(v) => match self.tools.get_page(v.folder_id as i32, v.name).await {
+                    Ok(v) => response_utils::ok!(serde_json::to_string(&v)?),
+                    Err(e) => error::response(e),
+                },
+            },
+            (&Method::GET, "history") => match query_to_struct::<PageQuery>(req.uri()) {
+                None => response_utils::malformed!(),
+                Some(v) => match self.tools.get_history(v.folder_id as i32, v.name).await {
+                    Ok(v) => response_utils::ok!(serde_json::to_string(&v)?),
+                    Err(e) => error::response(e),
                 },
             },
             (&Method::GET, "folder") => match query_to_struct::<FolderQuery>(req.uri()) {
                 None => response_utils::malformed!(),
-                Some(v) => match &self.tools.get_folder(v.folder_id as i32).await {
-                    Ok(v) => Response::builder()
-                        .header("Access-Control-Allow-Origin", "*")
-                        .body(Body::from(serde_json::to_string(v)?))?,
-                    Err(e) => response_utils::internal_error!(e),
+                Some(v) => match self.tools.get_folder(v.folder_id as i32).await {
+                    Ok(v) => response_utils::ok!(serde_json::to_string(&v)?),
+                    Err(e) => error::response(e),
                 },
             },
+            (&Method::GET, "tree") => {
+                match serde_qs::from_str::<IndexQuery>(req.uri().query().unwrap_or("")) {
+                    Err(_) => response_utils::malformed!(),
+                    Ok(q) => match self.tools.get_tree(q).await {
+                        Ok(v) => response_utils::ok!(serde_json::to_string(&v)?),
+                        Err(e) => error::response(e),
+                    },
+                }
+            }
+            (&Method::GET, "search") => {
+                match serde_qs::from_str::<SearchQuery>(req.uri().query().unwrap_or("")) {
+                    Err(_) => response_utils::malformed!(),
+                    Ok(q) if q.q.trim_start_matches('/').is_empty() => {
+                        ApiError::BadRequest("nothing to search for".into()).response()
+                    }
+                    Ok(q) => match self.tools.search(q).await {
+                        Ok(v) => response_utils::ok!(serde_json::to_string(&v)?),
+                        Err(e) => error::response(e),
+                    },
+                }
+            }
+
+            (&Method::POST, "page") => match (
+                query_to_struct::<PageQuery>(req.uri()),
+                form_to_struct::<MovePageForm>(&mut req).await?,
+            ) {
+                (Some(v), Some(f)) => {
+                    let to_folder_id = f.to_folder_id.unwrap_or(v.folder_id) as i32;
+                    let to_name = f.to_name.unwrap_or_else(|| v.name.clone());
+                    match self
+                        .tools
+                        .move_page(
+                            v.folder_id as i32,
+                            v.name.clone(),
+                            to_folder_id,
+                            to_name.clone(),
+                        )
+                        .await
+                    {
+                        Ok(_) => {
+                            self.audit(AuditEntry {
+                                target: Some(format!("{}/{}", v.folder_id, v.name)),
+                                after: Some(serde_json::json!({
+                                    "folder_id": to_folder_id,
+                                    "name": to_name,
+                                })),
+                                ..AuditEntry::new(auth.actor(), AuditAction::MovePage, ip)
+                            })
+                            .await;
+                            response_utils::ok!()
+                        }
+                        Err(e) => error::response(e),
+                    }
+                }
+                _ => response_utils::malformed!(),
+            },
+            (&Method::POST, "folder") => match (
+                query_to_struct::<FolderQuery>(req.uri()),
+                form_to_struct::<MoveFolderForm>(&mut req).await?,
+            ) {
+                (Some(v), Some(f)) => {
+                    let folder = self.tools.get_folder(v.folder_id as i32).await?;
+                    let to_parent_id = match (f.to_parent_id, folder.parent_id) {
+                        (Some(p), _) => p as i32,
+                        (None, Some(p)) => p,
+                        (None, None) => 0,
+                    };
+                    let to_name = f.to_name.unwrap_or_else(|| folder.name.clone());
+                    match self
+                        .tools
+                        .move_folder(v.folder_id as i32, to_parent_id, to_name.clone())
+                        .await
+                    {
+                        Ok(_) => {
+                            self.audit(AuditEntry {
+                                target: Some(v.folder_id.to_string()),
+                                before: Some(serde_json::json!({
+                                    "parent_id": folder.parent_id,
+                                    "name": folder.name,
+                                })),
+                                after: Some(serde_json::json!({
+                                    "parent_id": to_parent_id,
+                                    "name": to_name,
+                                })),
+                                ..AuditEntry::new(auth.actor(), AuditAction::MoveFolder, ip)
+                            })
+                            .await;
+                            response_utils::ok!()
+                        }
+                        Err(e) => error::response(e),
+                    }
+                }
+                _ => response_utils::malformed!(),
+            },
+            (&Method::POST, "merge") => match (
+                query_to_struct::<PageQuery>(req.uri()),
+                form_to_struct::<MergeForm>(&mut req).await?,
+            ) {
+                (Some(v), Some(f)) => {
+                    let before = match self
+                        .tools
+                        .get_page(v.folder_id as i32, v.name.clone())
+                        .await
+                    {
+                        Ok(p) => Some(serde_json::to_value(p)?),
+                        Err(_) => None,
+                    };
+                    match self
+                        .tools
+                        .merge_page(
+                            v.folder_id as i32,
+                            v.name.clone(),
+                            f.into_folder_id as i32,
+                            f.into_name.clone(),
+                        )
+                        .await
+                    {
+                        Ok(_) => {
+                            self.audit(AuditEntry {
+                                target: Some(format!("{}/{}", v.folder_id, v.name)),
+                                before,
+                                after: Some(serde_json::json!({
+                                    "folder_id": f.into_folder_id,
+                                    "name": f.into_name,
+                                })),
+                                ..AuditEntry::new(auth.actor(), AuditAction::MergePage, ip)
+                            })
+                            .await;
+                            response_utils::ok!()
+                        }
+                        Err(e) => error::response(e),
+                    }
+                }
+                _ => response_utils::malformed!(),
+            },
 
             (&Method::DELETE, "page") => match query_to_struct::<PageQuery>(req.uri()) {
                 None => response_utils::malformed!(),
-                Some(v) => match &self.tools.delete_page(v.folder_id as i32, v.name).await {
-                    Ok(v) => response_utils::ok!(serde_json::to_string(v)?),
-                    Err(e) => response_utils::internal_error!(e),
-                },
+                Some(v) => {
+                    let before = match self
+                        .tools
+                        .get_page(v.folder_id as i32, v.name.clone())
+                        .await
+                    {
+                        Ok(p) => Some(serde_json::to_value(p)?),
+                        Err(_) => None,
+                    };
+                    match self
+                        .tools
+                        .delete_page(v.folder_id as i32, v.name.clone())
+                        .await
+                    {
+                        Ok(r) => {
+                            self.audit(AuditEntry {
+                                target: Some(format!("{}/{}", v.folder_id, v.name)),
+                                before,
+                                ..AuditEntry::new(auth.actor(), AuditAction::DeletePage, ip)
+                            })
+                            .await;
+                            response_utils::ok!(serde_json::to_string(&r)?)
+                        }
+                        Err(e) => error::response(e),
+                    }
+                }
             },
             (&Method::DELETE, "folder") => match query_to_struct::<FolderQuery>(req.uri()) {
                 None => response_utils::malformed!(),
-                Some(v) => match &self.tools.delete_folder(v.folder_id as i32).await {
-                    Ok(v) => response_utils::ok!(serde_json::to_string(v)?),
-                    Err(e) => response_utils::internal_error!(e),
-                },
+                Some(v) => {
+                    let before = match self.tools.get_folder(v.folder_id as i32).await {
+                        Ok(f) => Some(serde_json::to_value(f)?),
+                        Err(_) => None,
+                    };
+                    match self.tools.delete_folder(v.folder_id as i32).await {
+                        Ok(r) => {
+                            self.audit(AuditEntry {
+                                target: Some(v.folder_id.to_string()),
+                                before,
+                                ..AuditEntry::new(auth.actor(), AuditAction::DeleteFolder, ip)
+                            })
+                            .await;
+                            response_utils::ok!(serde_json::to_string(&r)?)
+                        }
+                        Err(e) => error::response(e),
+                    }
+                }
             },
 
             (&Method::GET, "settings") => {
                 response_utils::ok!(serde_json::to_string(&self.tools.get_settings().await?)?)
             }
             (&Method::POST, "settings") => {
-                match serde_qs::from_bytes::<'_, DenViewSettings>(&to_bytes(req.body_mut()).await?)
+                // non-strict, since browsers percent-encode the brackets
+                // in list fields
+                match serde_qs::Config::new(5, false)
+                    .deserialize_bytes::<DenViewSettings>(&to_bytes(req.body_mut()).await?)
                 {
-                    Err(e) => response_utils::internal_error!(e),
-                    Ok(s) => match self.tools.update_settings(s).await {
-                        Err(e) => response_utils::internal_error!(e),
-                        Ok(_) => response_utils::ok!("settings updated - please restart denViews!"),
+                    Err(e) => error::response(Box::new(e)),
+                    Ok(mut s) => {
+                        s.cors_origins.retain(|o| !o.trim().is_empty());
+                        s.path_rules.retain(|r| !r.trim().is_empty());
+                        s.path_normalization.retain(|n| !n.trim().is_empty());
+                        s.path_rewrites.retain(|r| !r.trim().is_empty());
+                        s.query_params.retain(|q| !q.trim().is_empty());
+                        s.exclude_paths.retain(|p| !p.trim().is_empty());
+                        s.exclude_queries.retain(|q| !q.trim().is_empty());
+                        s.exclude_ips.retain(|i| !i.trim().is_empty());
+                        let before = serde_json::to_value(self.tools.get_settings().await?)?;
+                        let after = serde_json::to_value(&s)?;
+                        match self.tools.update_settings(s).await {
+                            Err(e) => error::response(e),
+                            Ok(_) => {
+                                self.audit(AuditEntry {
+                                    before: Some(before),
+                                    after: Some(after),
+                                    ..AuditEntry::new(auth.actor(), AuditAction::UpdateSettings, ip)
+                                })
+                                .await;
+                                response_utils::ok!("settings updated")
+                            }
+                        }
+                    }
+                }
+            }
+
+            (&Method::GET, "session") => match auth {
+                Auth::Session(s) => response_utils::ok!(serde_json::to_string(s)?),
+                _ => response_utils::not_found!(),
+            },
+            (&Method::POST, "logout") => {
+                if let Some(session) = auth::session_cookie(&req) {
+                    self.tools
+                        .delete_session(util::hash_token(&session))
+                        .await?;
+                }
+
+                Response::builder()
+                    .status(303)
+                    .header(LOCATION, "/_denViews_dash/login")
+                    .header(SET_COOKIE, auth::clear_session_cookie())
+                    .body(Body::from(""))?
+            }
+
+            (&Method::POST, "credentials") => {
+                let res: Result<CredentialsForm, serde_qs::Error> =
+                    serde_qs::from_bytes(&to_bytes(req.body_mut()).await?);
+                match res {
+                    Err(_) => response_utils::malformed!(),
+                    Ok(v) if v.pass.is_empty() => response_utils::malformed!(),
+                    Ok(v) => match self.tools.check_password(v.current_pass).await? {
+                        false => response_utils::forbidden!(),
+                        true => {
+                            let user = match v.user.is_empty() {
+                                true => None,
+                                false => Some(v.user),
+                            };
+                            self.tools.set_credentials(user.clone(), v.pass).await?;
+                            self.audit(AuditEntry {
+                                target: user,
+                                ..AuditEntry::new(auth.actor(), AuditAction::UpdateCredentials, ip)
+                            })
+                            .await;
+
+                            Response::builder()
+                                .header(SET_COOKIE, auth::clear_session_cookie())
+                                .body(Body::from(
+                                    "credentials updated - all sessions and API tokens have been revoked",
+                                ))?
+                        }
                     },
                 }
             }
 
-            _ => response_utils::response_with_code!(405, "Not allowed."),
+            (&Method::GET, "tokens") => {
+                response_utils::ok!(serde_json::to_string(&self.tools.get_tokens().await?)?)
+            }
+            (&Method::POST, "tokens") => {
+                let res: Result<ApiTokenRequest, serde_qs::Error> = serde_qs::Config::new(5, false)
+                    .deserialize_bytes(&to_bytes(req.body_mut()).await?);
+                match res {
+                    Err(e) => error::response(Box::new(e)),
+                    Ok(v) if v.name.is_empty() || v.scopes.is_empty() => {
+                        response_utils::malformed!()
+                    }
+                    Ok(v) => {
+                        let token = util::create_token();
+                        match self.tools.create_token(v, util::hash_token(&token)).await {
+                            Ok(record) => {
+                                self.audit(AuditEntry {
+                                    target: Some(record.name.clone()),
+                                    after: Some(serde_json::to_value(&record)?),
+                                    ..AuditEntry::new(auth.actor(), AuditAction::CreateToken, ip)
+                                })
+                                .await;
+                                response_utils::ok!(serde_json::to_string(&CreatedToken {
+                                    token,
+                                    record
+                                })?)
+                            }
+                            Err(e) => error::response(e),
+                        }
+                    }
+                }
+            }
+            (&Method::DELETE, "tokens") => match query_to_struct::<TokenQuery>(req.uri()) {
+                None => response_utils::malformed!(),
+                Some(v) => match self.tools.delete_token(v.token_id).await {
+                    Ok(_) => {
+                        self.audit(AuditEntry {
+                            target: Some(v.token_id.to_string()),
+                            ..AuditEntry::new(auth.actor(), AuditAction::DeleteToken, ip)
+                        })
+                        .await;
+                        response_utils::ok!()
+                    }
+                    Err(e) => error::response(e),
+                },
+            },
+
+            (&Method::GET, "audit") => {
+                match serde_qs::from_str::<AuditQuery>(req.uri().query().unwrap_or("")) {
+                    Err(_) => response_utils::malformed!(),
+                    Ok(q) => {
+                        response_utils::ok!(serde_json::to_string(&self.tools.get_audit(q).await?)?)
+                    }
+                }
+            }
+
+            _ => ApiError::MethodNotAllowed.response(),
         })
     }
 }
 
+fn dash_path(req: &Request<Body>) -> Vec<String> {
+    req.uri()
+        .path()
+        .get(1..)
+        .unwrap_or("")
+        .split('/')
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>()
+}
+
+// Form bodies are parsed non-strictly, the same as settings, and may
+// carry a csrf_token alongside their own fields.
+async fn form_to_struct<T: serde::de::DeserializeOwned>(
+    req: &mut Request<Body>,
+) -> Result<Option<T>, Error> {
+    let body = to_bytes(req.body_mut()).await?;
+    Ok(serde_qs::Config::new(5, false)
+        .deserialize_bytes::<T>(&body)
+        .ok())
+}
+
 fn query_to_struct<'de, T: serde::Deserialize<'de>>(uri: &'de Uri) -> Option<T> {
     match uri.path_and_query() {
         None => None,
@@ -0,0 +1,30 @@
+// tracker
+//
+// The tracking script that sites embed, served from
+// /_denViews_script.js. It's also served from a URL with its content
+// hash in it, /_denViews_script.<hash>.js, which can be cached forever
+// since a new script always has a new URL.
+
+#[derive(rust_embed::RustEmbed)]
+#[folder = "tracker/"]
+struct ScriptResources;
+
+const SCRIPT: &str = "denviews.js";
+
+pub struct Script {
+    pub data: std::borrow::Cow<'static, [u8]>,
+    pub hash: String,
+}
+
+pub fn get_script() -> Option<Script> {
+    let file = ScriptResources::get(SCRIPT)?;
+    let hash = file.metadata.sha256_hash()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    Some(Script {
+        data: file.data,
+        hash,
+    })
+}
@@ -43,9 +43,13 @@ pub fn bytes_to_base64(mut bytes: Vec<u8>) -> String {
     result
 }
 
-pub fn base64_to_bytes(encoded: String) -> Vec<u8> {
+// base64_to_bytes
+//
+// Input comes from outside (auth headers, Lambda bodies), so anything
+// that isn't valid base64 is None instead of a panic.
+pub fn base64_to_bytes(encoded: &str) -> Option<Vec<u8>> {
     let mut padding = 0;
-    let mut res = encoded
+    let res = encoded
         .as_bytes()
         .chunks(4)
         .map(|c| {
@@ -85,7 +89,7 @@ pub fn base64_to_bytes(encoded: String) -> Vec<u8> {
                 }
 
                 if count > 0 && h[e.0] == 0 {
-                    panic!("could not find e in INDEX: {}", *e.1 as char);
+                    return None;
                 }
             }
 
@@ -100,21 +104,21 @@ pub fn base64_to_bytes(encoded: String) -> Vec<u8> {
             n[1] = ((u << 16) >> 24) as u8;
             n[2] = ((u << 24) >> 24) as u8;
 
-            n
+            Some(n)
         })
-        .flatten()
-        .collect::<Vec<u8>>();
+        .collect::<Option<Vec<[u8; 3]>>>()?;
 
     if padding > 2 {
-        panic!("malformed base64 string")
+        return None;
     }
 
+    let mut res = res.concat();
     while padding > 0 {
         res.pop();
         padding -= 1;
     }
 
-    res
+    Some(res)
 }
 
 #[cfg(test)]
@@ -129,25 +133,27 @@ mod tests {
     fn test_base64_encoding_decoding() {
         assert_eq!(
             bytes_to_base64("r".as_bytes().to_vec()),
-            bytes_to_base64(base64_to_bytes("cg==".into()))
+            bytes_to_base64(base64_to_bytes("cg==").unwrap())
         );
         assert_eq!(
             bytes_to_base64(TEST_STRING_1.as_bytes().to_vec()),
-            bytes_to_base64(base64_to_bytes(
-                "U2FsbHkgc2VsbHMgc2VhIHNoZWxscyBieSB0aGUgc2VhIHNob3Jl".into()
-            ))
+            bytes_to_base64(
+                base64_to_bytes("U2FsbHkgc2VsbHMgc2VhIHNoZWxscyBieSB0aGUgc2VhIHNob3Jl").unwrap()
+            )
         );
         assert_eq!(
             bytes_to_base64(TEST_STRING_2.as_bytes().to_vec()),
-            bytes_to_base64(base64_to_bytes(
-                "S05UT0JUVVQsIHRoZSB1bmlmaWNhdGlvbiBvZiBLTlRPIGFuZCBCVFVU".into()
-            ))
+            bytes_to_base64(
+                base64_to_bytes("S05UT0JUVVQsIHRoZSB1bmlmaWNhdGlvbiBvZiBLTlRPIGFuZCBCVFVU")
+                    .unwrap()
+            )
         );
         assert_eq!(
             bytes_to_base64(TEST_STRING_3.as_bytes().to_vec()),
-            bytes_to_base64(base64_to_bytes(
-                "aG93IGRpZCBpIGdldCBoZXJlIGkgYW0gbm90IGdvb2Qgd2l0aCBjb21wdXRlcg==".into()
-            ))
+            bytes_to_base64(
+                base64_to_bytes("aG93IGRpZCBpIGdldCBoZXJlIGkgYW0gbm90IGdvb2Qgd2l0aCBjb21wdXRlcg==")
+                    .unwrap()
+            )
         );
     }
 }
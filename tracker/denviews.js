@@ -0,0 +1,112 @@
+// denViews tracker
+//
+// <script async src="https://views.example.com/_denViews_script.js"></script>
+//
+// Sends a view for the current page when it loads, and again whenever a
+// single-page app moves to another page through the History API. Views go
+// to whichever denViews served this script.
+//
+// - `data-denviews-opt-out` on this script tag, or on <html>, stops views
+//   from being sent; counters are still filled in
+// - any element with `data-denviews-counter` has its text replaced with
+//   the page's views, or its hits if the attribute is `hits`
+(function () {
+  'use strict';
+
+  var script = document.currentScript;
+  if (!script || !script.src) {
+    return;
+  }
+
+  var base = new URL(script.src).origin;
+  var last = null;
+
+  function optedOut() {
+    return script.hasAttribute('data-denviews-opt-out') ||
+      document.documentElement.hasAttribute('data-denviews-opt-out');
+  }
+
+  function page() {
+    return location.pathname + location.search;
+  }
+
+  function send(url) {
+    try {
+      if (navigator.sendBeacon && navigator.sendBeacon(url)) {
+        return;
+      }
+    } catch (e) {
+      // some browsers throw instead of returning false
+    }
+
+    fetch(url, { method: 'POST', keepalive: true, mode: 'no-cors' })
+      .catch(function () {});
+  }
+
+  function fill() {
+    var counters = document.querySelectorAll('[data-denviews-counter]');
+    if (counters.length === 0) {
+      return;
+    }
+
+    fetch(base + page(), { method: 'GET' })
+      .then(function (res) {
+        return res.ok ? res.json() : { views: 0, hits: 0 };
+      })
+      .then(function (record) {
+        counters.forEach(function (el) {
+          var metric = el.getAttribute('data-denviews-counter') === 'hits'
+            ? 'hits'
+            : 'views';
+          el.textContent = String(record[metric] || 0);
+        });
+      })
+      .catch(function () {});
+  }
+
+  function track() {
+    var current = page();
+    // replaceState, and pushState to the same page, aren't new views
+    if (current === last) {
+      return;
+    }
+    last = current;
+
+    if (!optedOut()) {
+      send(base + current);
+    }
+
+    fill();
+  }
+
+  function whenVisible() {
+    // pages that are only being prerendered haven't been seen yet
+    if (document.visibilityState === 'prerender') {
+      document.addEventListener('visibilitychange', function wait() {
+        if (document.visibilityState !== 'prerender') {
+          document.removeEventListener('visibilitychange', wait);
+          track();
+        }
+      });
+      return;
+    }
+
+    track();
+  }
+
+  ['pushState', 'replaceState'].forEach(function (name) {
+    var original = history[name];
+    history[name] = function () {
+      var result = original.apply(this, arguments);
+      track();
+      return result;
+    };
+  });
+  window.addEventListener('popstate', track);
+
+  if (document.readyState === 'loading') {
+    document.addEventListener('DOMContentLoaded', whenVisible);
+  } else {
+    whenVisible();
+  }
+})();